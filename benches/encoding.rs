@@ -0,0 +1,73 @@
+use byte_assembly::{Encoding, Program, SaveOptions};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::fmt::Write;
+
+/// Generates a large program, using many different registers and numbers
+fn large_program() -> String {
+    let mut code = String::from("mov r0, 0\nloop:\n");
+    for index in 0..10_000 {
+        let register = index % 256;
+        writeln!(code, "    add r{register}, r{register}, {index}").unwrap();
+        if index % 100 == 0 {
+            writeln!(
+                code,
+                "    /* Block {index} */\n    out \"{{{register}}}\\n\""
+            )
+            .unwrap();
+        }
+    }
+    code.push_str("    cmp r0, 10\n    jl loop\n");
+    code
+}
+
+fn encoding(c: &mut Criterion) {
+    let programs = [
+        (
+            "prime_generator",
+            include_str!("../tests/branching/prime_generator.basm").to_owned(),
+        ),
+        ("large", large_program()),
+    ];
+    let encodings = [
+        ("postcard", Encoding::Postcard),
+        ("compact", Encoding::Compact),
+    ];
+
+    let mut encode = c.benchmark_group("encode");
+    for (name, code) in &programs {
+        let program = Program::assemble(code).unwrap();
+        for (encoding_name, encoding) in encodings {
+            let options = SaveOptions {
+                encoding,
+                ..SaveOptions::default()
+            };
+            // The throughput shows the size of the bytecode next to the time
+            let size = program.to_bytes_with(&options).unwrap().len();
+            encode.throughput(Throughput::Bytes(size as u64));
+            encode.bench_function(BenchmarkId::new(encoding_name, name), |b| {
+                b.iter(|| program.to_bytes_with(black_box(&options)).unwrap());
+            });
+        }
+    }
+    encode.finish();
+
+    let mut decode = c.benchmark_group("decode");
+    for (name, code) in &programs {
+        let program = Program::assemble(code).unwrap();
+        for (encoding_name, encoding) in encodings {
+            let options = SaveOptions {
+                encoding,
+                ..SaveOptions::default()
+            };
+            let bytes = program.to_bytes_with(&options).unwrap();
+            decode.throughput(Throughput::Bytes(bytes.len() as u64));
+            decode.bench_function(BenchmarkId::new(encoding_name, name), |b| {
+                b.iter(|| Program::from_bytes(black_box(&bytes)).unwrap());
+            });
+        }
+    }
+    decode.finish();
+}
+
+criterion_group!(benches, encoding);
+criterion_main!(benches);
@@ -1,24 +1,41 @@
-use crate::token::{
-    argument::{read_arguments, skip_whitespace},
-    Label,
+use crate::{
+    execute::MAX_MEMORY_SIZE,
+    token::{
+        argument::{read_arguments, skip_whitespace},
+        directive::Directive,
+        Label,
+    },
 };
 
 use super::Token;
 use code::Code;
-use error::Error;
-use std::collections::HashMap;
+use error::{closest, Error, ErrorKind};
+use span::Span;
+use std::collections::{HashMap, HashSet};
 
-pub mod code;
+pub(crate) mod code;
+pub mod diagnostic;
 pub mod error;
+pub mod span;
 
-/// Reads a multi-line comment
-fn read_comment(code: &mut Code) -> Result<Token, Error> {
+/// The mnemonics of all instructions
+pub const MNEMONICS: [&str; 28] = [
+    "out", "mov", "add", "sub", "mul", "div", "mod", "and", "or", "xor", "not", "shl", "shr",
+    "sar", "rol", "ror", "cmp", "jmp", "jl", "jg", "je", "call", "ret", "push", "pop", "peek",
+    "load", "store",
+];
+
+/// The names of all directives
+pub const DIRECTIVES: [&str; 6] = [".data", ".text", ".word", ".byte", ".ascii", ".zero"];
+
+/// Reads a multi-line comment, starting at the passed span
+fn read_comment(code: &mut Code, start: Span) -> Result<Token, Error> {
     // Create a buffer for the string content
     let mut comment = String::new();
 
     // Read the first char of the string, error if EOF is reached
     let Some(mut last_char) = code.next() else {
-        return Err(Error::end_of_file(code.line(), code.column()));
+        return Err(Error::end_of_file(start.until(code.end())));
     };
 
     // Iterate over the chars in the comment
@@ -35,7 +52,7 @@ fn read_comment(code: &mut Code) -> Result<Token, Error> {
 
     // Error, if the end of the code was reached before the comment was closed
     if code.eof() {
-        return Err(Error::end_of_file(code.line(), code.column()));
+        return Err(Error::end_of_file(start.until(code.end())));
     }
 
     // Return the read comment
@@ -43,13 +60,15 @@ fn read_comment(code: &mut Code) -> Result<Token, Error> {
 }
 
 /// Reads a string
-fn read_string(code: &mut Code) -> Result<String, Error> {
+pub(crate) fn read_string(code: &mut Code) -> Result<String, Error> {
     // Create a buffer for the string and a variable to keep track of escaped chars
     let mut result = String::new();
     let mut escaped = false;
+    let start = code.span();
+    let mut escape = start;
 
     // Iterate over the chars
-    for c in code.by_ref() {
+    while let Some(c) = code.next() {
         if escaped {
             // If the current char is escaped, try converting it to the correct character
             // Error, if it is an invalid escape character
@@ -60,11 +79,12 @@ fn read_string(code: &mut Code) -> Result<String, Error> {
                 '\\' | '"' => c,
                 't' => '\t',
                 '0' => '\0',
-                _ => panic!(
-                    "Invalid escape character '{c}': {}:{}",
-                    code.line(),
-                    code.column()
-                ),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidEscape(c),
+                        escape.to(code.span()),
+                    ))
+                }
             });
 
             // Set escaped to false to not escape more characters than needed
@@ -75,6 +95,7 @@ fn read_string(code: &mut Code) -> Result<String, Error> {
         } else if c == '\\' {
             // If the current character is a '\', the next is escaped.
             escaped = true;
+            escape = code.span();
         } else {
             // Push the current character to the string in all other cases
             result.push(c);
@@ -83,7 +104,7 @@ fn read_string(code: &mut Code) -> Result<String, Error> {
 
     // Error, if the end of the code is reached before finding a double quote
     if code.eof() {
-        Err(Error::end_of_file(code.line(), code.column()))
+        Err(Error::end_of_file(start.until(code.end())))
     } else {
         Ok(result)
     }
@@ -94,7 +115,7 @@ fn read_out(code: &mut Code) -> Result<Token, Error> {
     // Skip all whitespace
     // Error, if the end of the file was reached
     let Some(c) = skip_whitespace(code)? else {
-        return Err(Error::end_of_file(code.line(), code.column()));
+        return Err(Error::end_of_file(code.span()));
     };
 
     // If the current char is a double quote, the value is a string
@@ -119,25 +140,34 @@ fn read_out(code: &mut Code) -> Result<Token, Error> {
     Ok(Token::Out(output))
 }
 
-/// Reads labels for branching
-fn read_jmp_label(code: &mut Code) -> Result<String, Error> {
+/// Reads labels for branching, storing the label and its span to check whether it exists
+fn read_jmp_label(code: &mut Code, jumps: &mut Vec<(String, Span)>) -> Result<Label, Error> {
     // Skip all whitespace
     let Some(c) = skip_whitespace(code)? else {
-        return Err(Error::end_of_file(code.line(), code.column()));
+        return Err(Error::end_of_file(code.span()));
     };
 
     // Read the label
-    let label = code
-        .take_while(|c| !c.is_whitespace())
-        .fold(c.to_string(), |mut label, c| {
-            label.push(c);
-            label
-        });
-    Ok(label)
+    let mut label = c.to_string();
+    let mut span = code.span();
+    while let Some(c) = code.next() {
+        if c.is_whitespace() {
+            break;
+        }
+        label.push(c);
+        span = span.to(code.span());
+    }
+    jumps.push((label.clone(), span));
+    Ok(Label::Base(label))
 }
 
-/// Parses the passed command
-fn parse_command(command: &str, code: &mut Code) -> Result<Option<Token>, Error> {
+/// Parses the passed command, located at the passed span
+fn parse_command(
+    command: &str,
+    span: Span,
+    code: &mut Code,
+    jumps: &mut Vec<(String, Span)>,
+) -> Result<Option<Token>, Error> {
     match command {
         "" => Ok(None),
         "out" => Ok(Some(read_out(code)?)),
@@ -147,81 +177,323 @@ fn parse_command(command: &str, code: &mut Code) -> Result<Option<Token>, Error>
         "mul" => Ok(Some(Token::mul(code)?)),
         "div" => Ok(Some(Token::div(code)?)),
         "mod" => Ok(Some(Token::modulo(code)?)),
-        "jmp" => Ok(Some(Token::Jmp(Label::Base(read_jmp_label(code)?)))),
-        "jl" => Ok(Some(Token::Jl(Label::Base(read_jmp_label(code)?)))),
-        "jg" => Ok(Some(Token::Jg(Label::Base(read_jmp_label(code)?)))),
-        "je" => Ok(Some(Token::Je(Label::Base(read_jmp_label(code)?)))),
+        "and" => Ok(Some(Token::and(code)?)),
+        "or" => Ok(Some(Token::or(code)?)),
+        "xor" => Ok(Some(Token::xor(code)?)),
+        "not" => Ok(Some(Token::not(code)?)),
+        "shl" => Ok(Some(Token::shl(code)?)),
+        "shr" => Ok(Some(Token::shr(code)?)),
+        "sar" => Ok(Some(Token::sar(code)?)),
+        "rol" => Ok(Some(Token::rol(code)?)),
+        "ror" => Ok(Some(Token::ror(code)?)),
+        "jmp" => Ok(Some(Token::Jmp(read_jmp_label(code, jumps)?))),
+        "jl" => Ok(Some(Token::Jl(read_jmp_label(code, jumps)?))),
+        "jg" => Ok(Some(Token::Jg(read_jmp_label(code, jumps)?))),
+        "je" => Ok(Some(Token::Je(read_jmp_label(code, jumps)?))),
+        "call" => Ok(Some(Token::Call(read_jmp_label(code, jumps)?))),
+        "ret" => Ok(Some(Token::Ret)),
+        "push" => Ok(Some(Token::push(code)?)),
+        "pop" => Ok(Some(Token::pop(code)?)),
+        "peek" => Ok(Some(Token::peek(code)?)),
+        "load" => Ok(Some(Token::load(code)?)),
+        "store" => Ok(Some(Token::store(code)?)),
         "cmp" => {
             let args = read_arguments::<2>(code)?;
             Ok(Some(Token::Cmp(args[0], args[1])))
         }
         label if label.ends_with(':') => {
             let label_name = label.chars().take_while(|c| *c != ':').collect::<String>();
-            assert_eq!(
-                label.len(),
-                label_name.len() + 1,
-                "Invalid label \"{command}\" at: {}:{}",
-                code.line(),
-                code.column()
-            );
+
+            // Error, if the label is empty or contains multiple ':'
+            if label_name.is_empty() || label.len() != label_name.len() + 1 {
+                return Err(Error::new(
+                    ErrorKind::MalformedLabel(command.to_owned()),
+                    span,
+                ));
+            }
             Ok(Some(Token::Label(label_name)))
         }
-        _ => panic!(
-            "Invalid command \"{command}\" at: {}:{}",
-            code.line(),
-            code.column()
+        directive if directive.starts_with('.') => Directive::read(directive, code)?
+            .map(|directive| Some(Token::Directive(directive)))
+            .ok_or_else(|| {
+                Error::new(ErrorKind::UnknownDirective(command.to_owned()), span)
+                    .with_suggestion(closest(command, DIRECTIVES))
+            }),
+        _ => Err(
+            Error::new(ErrorKind::UnknownMnemonic(command.to_owned()), span)
+                .with_suggestion(closest(command, MNEMONICS)),
         ),
     }
 }
 
+/// Stores the error and skips the rest of the line, so parsing can continue on the next line.
+/// Returns the value on success.
+fn recover<T>(result: Result<T, Error>, errors: &mut Vec<Error>, code: &mut Code) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(error) => {
+            errors.push(error);
+            code.skip_line();
+            None
+        }
+    }
+}
+
 /// Splits the code into tokens
-pub fn split_tokens(code: &str) -> Result<Vec<Token>, Error> {
-    // Create a new code iterator and a vector for the tokens
+///
+/// # Errors
+/// Returns every error found in the code, sorted by position, if it isn't valid byte assembly.
+pub fn split_tokens(code: &str) -> Result<Vec<Token>, Vec<Error>> {
+    split_spanned_tokens(code).map(|tokens| tokens.into_iter().map(|(token, _)| token).collect())
+}
+
+/// Splits the code into tokens, each with the span of the code it was read from
+///
+/// # Errors
+/// Returns every error found in the code, sorted by position, if it isn't valid byte assembly.
+pub fn split_spanned_tokens(code: &str) -> Result<Vec<(Token, Span)>, Vec<Error>> {
+    let (tokens, errors) = read_spanned_tokens(code);
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Splits the code into tokens, each with the span of the code it was read from.
+/// Lines with errors are skipped, so the tokens of the other lines are still returned,
+/// together with every error found in the code, sorted by position.
+pub(crate) fn read_spanned_tokens(code: &str) -> (Vec<(Token, Span)>, Vec<Error>) {
+    // Create a new code iterator and vectors for the tokens and errors
     let mut code = Code::from_str(code);
     let mut tokens = Vec::new();
+    let mut jumps = Vec::new();
+    let mut errors = Vec::new();
 
     // If the code is empty, return the empty vector
     let Some(mut last_char) = code.next() else {
-        return Ok(tokens);
+        return (tokens, errors);
     };
 
-    // Create a command string
+    // Create a command string and store where it started
     let mut command = if last_char.is_whitespace() {
         String::new()
     } else {
         last_char.to_string()
     };
+    let mut span = code.span();
+    let mut last_span = span;
 
     // Iterate through the code
     while let Some(c) = code.next() {
         if c.is_whitespace() {
             // If the current char is whitespace
             // Try to parse the current command
-            if let Some(token) = parse_command(&command, &mut code)? {
-                tokens.push(token);
-            };
+            let token = parse_command(&command, span, &mut code, &mut jumps);
+            if let Some(Some(token)) = recover(token, &mut errors, &mut code) {
+                tokens.push((token, span.until(code.end())));
+            }
 
             // Clear the command
             command.clear();
         } else if last_char == '/' && c == '*' {
             // Else if the last and current char form the start of a comment
             // Try to push the comment to the token vector
-            tokens.push(read_comment(&mut code)?);
+            let comment = read_comment(&mut code, last_span);
+            if let Some(comment) = recover(comment, &mut errors, &mut code) {
+                tokens.push((comment, last_span.until(code.end())));
+            }
 
             // Clear the command
             command.clear();
         } else {
-            // Push the current char to the command
+            // Push the current char to the command, storing the start of new commands
+            if command.is_empty() {
+                span = code.span();
+            }
             command.push(c);
+            span = span.to(code.span());
         }
         // Set the current char as last char
         last_char = c;
+        last_span = code.span();
     }
     // Try to parse the current command if the command isn't empty
-    if let Some(token) = parse_command(&command, &mut code)? {
-        tokens.push(token);
+    let token = parse_command(&command, span, &mut code, &mut jumps);
+    if let Some(Some(token)) = recover(token, &mut errors, &mut code) {
+        tokens.push((token, span.until(code.end())));
+    }
+
+    // Make sure every instruction and data directive is in its section,
+    // and every label that is jumped to or used as address exists in the right section
+    let in_data = data_tokens(tokens.iter().map(|(token, _)| token));
+    errors.extend(check_sections(&tokens, &in_data));
+    errors.extend(check_jumps(&tokens, jumps, &in_data));
+    errors.extend(check_addresses(&tokens, &in_data));
+    errors.extend(check_data_size(&tokens, &in_data));
+    errors.sort_by_key(|error| error.span().start);
+    (tokens, errors)
+}
+
+/// Returns the names of the labels in the code or the data section
+fn section_labels<'a>(
+    tokens: &'a [(Token, Span)],
+    in_data: &'a [bool],
+    data: bool,
+) -> HashSet<&'a str> {
+    tokens
+        .iter()
+        .zip(in_data)
+        .filter_map(|((token, _), in_data)| match token {
+            Token::Label(label) if *in_data == data => Some(label.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns an error for every instruction in the data section and every data directive outside
+fn check_sections<'a>(
+    tokens: &'a [(Token, Span)],
+    in_data: &'a [bool],
+) -> impl Iterator<Item = Error> + 'a {
+    tokens
+        .iter()
+        .zip(in_data)
+        .filter_map(|((token, span), in_data)| {
+            let section = match token {
+                Token::Directive(directive) if directive.is_data() && !in_data => "code",
+                Token::Comment(_) | Token::Label(_) | Token::Directive(_) => return None,
+                _ if *in_data => "data",
+                _ => return None,
+            };
+            let found = token.to_string();
+            Some(Error::new(
+                ErrorKind::WrongSection { found, section },
+                *span,
+            ))
+        })
+}
+
+/// Returns an error for every branching instruction jumping to a label that doesn't exist
+fn check_jumps<'a>(
+    tokens: &'a [(Token, Span)],
+    jumps: Vec<(String, Span)>,
+    in_data: &'a [bool],
+) -> impl Iterator<Item = Error> + 'a {
+    let labels = section_labels(tokens, in_data, false);
+    jumps.into_iter().filter_map(move |(label, span)| {
+        if labels.contains(label.as_str()) {
+            return None;
+        }
+        let suggestion = closest(&label, labels.iter().copied());
+        Some(Error::new(ErrorKind::UndefinedLabel(label), span).with_suggestion(suggestion))
+    })
+}
+
+/// Returns an error for every memory address using a label that isn't in the data section
+fn check_addresses<'a>(
+    tokens: &'a [(Token, Span)],
+    in_data: &'a [bool],
+) -> impl Iterator<Item = Error> + 'a {
+    let labels = section_labels(tokens, in_data, true);
+    tokens.iter().filter_map(move |(token, span)| {
+        let (Token::Load(_, address) | Token::Store(address, _)) = token else {
+            return None;
+        };
+        let label = address.label.as_deref()?;
+        if labels.contains(label) {
+            return None;
+        }
+        let suggestion = closest(label, labels.iter().copied());
+        Some(
+            Error::new(ErrorKind::UndefinedLabel(label.to_owned()), *span)
+                .with_suggestion(suggestion),
+        )
+    })
+}
+
+/// Returns an error for the first data directive that doesn't fit in the memory,
+/// as the data section can't be larger than [`MAX_MEMORY_SIZE`] bytes
+fn check_data_size(tokens: &[(Token, Span)], in_data: &[bool]) -> Option<Error> {
+    let mut size = 0_usize;
+    for ((token, span), in_data) in tokens.iter().zip(in_data) {
+        let Token::Directive(directive) = token else {
+            continue;
+        };
+        if !in_data {
+            continue;
+        }
+        let Some(new_size) = size
+            .checked_add(directive.size())
+            .filter(|size| *size <= MAX_MEMORY_SIZE)
+        else {
+            let data = match directive {
+                Directive::Zero(count) => count.to_string(),
+                directive => directive.name().to_owned(),
+            };
+            return Some(Error::new(ErrorKind::InvalidData(data), *span));
+        };
+        size = new_size;
     }
-    Ok(tokens)
+    None
+}
+
+/// Returns for every token whether it is part of the data section:
+/// the tokens after `.data` up to `.text`, and those directives themselves
+pub fn data_tokens<'a>(tokens: impl IntoIterator<Item = &'a Token>) -> Vec<bool> {
+    let mut data = false;
+    tokens
+        .into_iter()
+        .map(|token| match token {
+            Token::Directive(Directive::Data) => {
+                data = true;
+                true
+            }
+            Token::Directive(Directive::Text) => {
+                data = false;
+                true
+            }
+            _ => data,
+        })
+        .collect()
+}
+
+/// Separates the data section from the instructions.
+/// Returns the instructions, with the labels in memory addresses replaced by their address,
+/// and the bytes of the data section.
+#[must_use]
+pub fn split_data(tokens: Vec<Token>) -> (Vec<Token>, Vec<u8>) {
+    let in_data = data_tokens(&tokens);
+
+    // Lay out the data, storing the address of every label
+    let mut data = Vec::new();
+    let mut addresses = HashMap::new();
+    let mut instructions = Vec::new();
+    for (token, in_data) in tokens.into_iter().zip(in_data) {
+        match token {
+            Token::Label(label) if in_data => {
+                addresses.insert(label, i64::try_from(data.len()).unwrap_or(i64::MAX));
+            }
+            Token::Directive(directive) if in_data => directive.write_data(&mut data),
+            _ if in_data => {}
+            token => instructions.push(token),
+        }
+    }
+
+    // Add the address of the label to the offset of memory addresses
+    for token in &mut instructions {
+        if let Token::Load(_, address) | Token::Store(address, _) = token {
+            if let Some(offset) = address
+                .label
+                .as_ref()
+                .and_then(|label| addresses.get(label))
+            {
+                address.offset = address.offset.wrapping_add(*offset);
+                address.label = None;
+            }
+        }
+    }
+    (instructions, data)
 }
 
 fn read_labels(tokens: &[Token]) -> HashMap<String, usize> {
@@ -238,17 +510,19 @@ fn read_labels(tokens: &[Token]) -> HashMap<String, usize> {
         .collect::<HashMap<_, _>>()
 }
 
+/// Converts the label to the address of the token after it, labels that don't exist are kept as is
 fn convert_label(label: &Label, labels: &HashMap<String, usize>) -> Label {
     match label {
-        Label::Base(label) => Label::Address(
-            *labels
-                .get(label)
-                .unwrap_or_else(|| panic!("Label \"{label}\" doesn't exist")),
+        Label::Base(name) => labels.get(name).map_or_else(
+            || Label::Base(name.clone()),
+            |address| Label::Address(*address + 1),
         ),
         Label::Address(address) => Label::Address(*address),
     }
 }
 
+/// Replaces the label names used by branching instructions by the address execution continues at,
+/// the address of the token after the label
 pub fn convert_labels(tokens: &mut [Token]) {
     let labels = read_labels(tokens);
     for token in tokens {
@@ -257,8 +531,37 @@ pub fn convert_labels(tokens: &mut [Token]) {
             Token::Jmp(label) => *token = Token::Jmp(convert_label(label, &labels)),
             Token::Jl(label) => *token = Token::Jl(convert_label(label, &labels)),
             Token::Je(label) => *token = Token::Je(convert_label(label, &labels)),
+            Token::Call(label) => *token = Token::Call(convert_label(label, &labels)),
             Token::Jg(label) => *token = Token::Jg(convert_label(label, &labels)),
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{error::ErrorKind, read_spanned_tokens, split_tokens};
+
+    #[test]
+    fn reports_every_bad_line() {
+        let code = "mov r0, 1\nmvo r1, 2\nadd r2, r0, r300\nmov r3, 3\njmp missing\nmov r4, 4";
+        let (tokens, errors) = read_spanned_tokens(code);
+        let errors = errors
+            .iter()
+            .map(|error| (error.kind().clone(), error.line(), error.column()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                (ErrorKind::UnknownMnemonic("mvo".to_owned()), 2, 1),
+                (ErrorKind::InvalidRegister("r300".to_owned()), 3, 13),
+                (ErrorKind::UndefinedLabel("missing".to_owned()), 5, 5),
+            ]
+        );
+
+        // The good lines are still read
+        let lines = tokens.iter().map(|(_, span)| span.line).collect::<Vec<_>>();
+        assert_eq!(lines, [1, 4, 5, 6]);
+        assert_eq!(split_tokens(code).unwrap_err().len(), 3);
+    }
+}
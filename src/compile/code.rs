@@ -1,21 +1,39 @@
+use super::span::Span;
 use std::str::Chars;
 
 /// An iterator over the characters in a piece of code
 pub struct Code<'a> {
     line: usize,
     column: usize,
+    offset: usize,
+    end: usize,
     buffer: Chars<'a>,
     last: Option<char>,
+    span: Span,
 }
 
 // Gives iterator functionality to Code
-impl<'a> Iterator for Code<'a> {
+impl Iterator for Code<'_> {
     type Item = char;
 
     fn next(&mut self) -> Option<Self::Item> {
         // Get the current character
         let result = self.buffer.next();
 
+        // Store the position of the character, before moving past it
+        // The span is empty at the end of the code
+        let length = result.map_or(0, char::len_utf8);
+        self.span = Span {
+            start: self.offset,
+            end: self.offset + length,
+            line: self.line,
+            column: self.column + 1,
+        };
+        self.offset = self.span.end;
+        if result.is_some_and(|c| !c.is_whitespace()) {
+            self.end = self.offset;
+        }
+
         // If it's a new-line character, add 1 to the line number and set column to 0
         // Otherwise, move to the next column
         if result == Some('\n') {
@@ -37,19 +55,44 @@ impl<'a> Code<'a> {
         Self {
             line: 1,
             column: 0,
+            offset: 0,
+            end: 0,
             buffer: s.chars(),
             last: None,
+            span: Span {
+                start: 0,
+                end: 0,
+                line: 1,
+                column: 1,
+            },
         }
     }
 
-    /// Returns the line number
-    pub const fn line(&self) -> usize {
-        self.line
+    /// Returns the span of the last read character
+    pub const fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns the next character, without reading it
+    pub fn peek(&self) -> Option<char> {
+        self.buffer.clone().next()
     }
 
-    /// Returns the column number
-    pub const fn column(&self) -> usize {
-        self.column
+    /// Returns the byte offset after the last read non-whitespace character
+    pub const fn end(&self) -> usize {
+        self.end
+    }
+
+    /// Skips the rest of the current line.
+    /// Nothing is skipped if the last read character already ended the line.
+    pub fn skip_line(&mut self) {
+        if self.last != Some('\n') {
+            for c in self.by_ref() {
+                if c == '\n' {
+                    break;
+                }
+            }
+        }
     }
 
     /// Returns whether the end of the file was reached
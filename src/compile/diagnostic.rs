@@ -0,0 +1,279 @@
+use super::{
+    error::{Error, Severity},
+    span::Span,
+};
+use serde::Serialize;
+use std::fmt::Write as _;
+
+/// ANSI escape codes used to color diagnostics
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders errors like rustc does.
+/// Prints the line of code containing the error, underlines the span of the error and adds a help note.
+#[derive(Debug, Clone, Copy)]
+pub struct Renderer<'a> {
+    source: &'a str,
+    file: &'a str,
+    color: bool,
+}
+
+impl<'a> Renderer<'a> {
+    /// Creates a renderer for errors in the passed code, read from the passed file
+    #[must_use]
+    pub const fn new(source: &'a str, file: &'a str) -> Self {
+        Self {
+            source,
+            file,
+            color: false,
+        }
+    }
+
+    /// Sets whether ANSI colors should be used
+    #[must_use]
+    pub const fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Returns the escape code if colors are enabled, an empty string otherwise
+    const fn paint(&self, code: &'static str) -> &'static str {
+        if self.color {
+            code
+        } else {
+            ""
+        }
+    }
+
+    /// Returns the line containing the start of the span
+    fn line_of(&self, span: Span) -> &'a str {
+        let start = span.start.min(self.source.len());
+        let line_start = self.source[..start]
+            .rfind('\n')
+            .map_or(0, |index| index + 1);
+        let line_end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |index| start + index);
+        self.source[line_start..line_end].trim_end_matches('\r')
+    }
+
+    /// Renders the error
+    #[must_use]
+    pub fn render(&self, error: &Error) -> String {
+        let (red, blue, bold, reset) = (
+            self.paint(RED),
+            self.paint(BLUE),
+            self.paint(BOLD),
+            self.paint(RESET),
+        );
+        let span = error.span();
+        let line = self.line_of(span);
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        // Print the error message and location
+        let mut output = String::new();
+        writeln!(output, "{red}error{reset}{bold}: {}{reset}", error.kind()).unwrap();
+        writeln!(
+            output,
+            "{gutter}{blue}-->{reset} {}:{}:{}",
+            self.file, span.line, span.column
+        )
+        .unwrap();
+
+        // Print the line of code
+        writeln!(output, "{gutter} {blue}|{reset}").unwrap();
+        writeln!(output, "{blue}{number} |{reset} {line}").unwrap();
+
+        // Underline the span, keeping tabs to stay aligned with the code
+        let padding = line
+            .chars()
+            .take(span.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let width = line
+            .chars()
+            .skip(span.column.saturating_sub(1))
+            .scan(0, |length, c| {
+                *length += c.len_utf8();
+                Some(*length)
+            })
+            .take_while(|length| *length <= span.len())
+            .count()
+            .max(1);
+        writeln!(
+            output,
+            "{gutter} {blue}|{reset} {padding}{red}{}{reset}",
+            "^".repeat(width)
+        )
+        .unwrap();
+
+        // Print the help note, if there is one
+        if let Some(help) = error.kind().help() {
+            writeln!(output, "{gutter} {blue}={reset} {bold}help{reset}: {help}").unwrap();
+        }
+
+        // Print the suggested replacement, if there is one
+        if let Some(suggestion) = error.suggestion() {
+            writeln!(
+                output,
+                "{gutter} {blue}={reset} {bold}help{reset}: did you mean `{suggestion}`?"
+            )
+            .unwrap();
+        }
+        output
+    }
+}
+
+/// A replacement for part of the code, that would probably fix the diagnostic
+#[derive(Debug, Clone, Serialize)]
+pub struct Suggestion {
+    pub message: String,
+    pub replacement: String,
+    pub span: Span,
+}
+
+/// A machine-readable diagnostic, serialized as a single JSON object
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub help: Option<String>,
+    pub file: String,
+    pub span: Option<Span>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic for an error that isn't caused by a specific part of the code
+    #[must_use]
+    pub fn new(message: String, file: &str) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: None,
+            message,
+            help: None,
+            file: file.to_owned(),
+            span: None,
+            suggestions: Vec::new(),
+        }
+    }
+
+    /// Creates a diagnostic for a compilation error in the passed file
+    #[must_use]
+    pub fn from_error(error: &Error, file: &str) -> Self {
+        let suggestions = error
+            .suggestion()
+            .map(|replacement| Suggestion {
+                message: format!("did you mean `{replacement}`?"),
+                replacement: replacement.to_owned(),
+                span: error.span(),
+            })
+            .into_iter()
+            .collect();
+        Self {
+            severity: error.kind().severity(),
+            code: Some(error.kind().code()),
+            message: error.kind().to_string(),
+            help: error.kind().help(),
+            file: file.to_owned(),
+            span: Some(error.span()),
+            suggestions,
+        }
+    }
+
+    /// Returns the diagnostic as a single line of JSON
+    ///
+    /// # Panics
+    /// Never panics, as diagnostics only contain strings and numbers.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Diagnostics can always be serialized")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, Renderer};
+    use crate::compile::split_tokens;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn underlines_the_error() {
+        let source = "mov r0, 1\n\tadd r1, r0, r300\n";
+        let errors = split_tokens(source).unwrap_err();
+        let output = Renderer::new(source, "code.basm").render(&errors[0]);
+        assert_eq!(
+            output,
+            concat!(
+                "error: Invalid register id \"r300\"\n",
+                " --> code.basm:2:14\n",
+                "  |\n",
+                "2 | \tadd r1, r0, r300\n",
+                "  | \t            ^^^^\n",
+                "  = help: registers are r0..r255, or sp for r255\n",
+            )
+        );
+    }
+
+    #[test]
+    fn suggests_replacements() {
+        let source = format!("{}mvo r1, 2", "mov r0, 1\n".repeat(9));
+        let errors = split_tokens(&source).unwrap_err();
+        let output = Renderer::new(&source, "code.basm").render(&errors[0]);
+        let help = errors[0].kind().help().unwrap();
+        assert_eq!(
+            output,
+            format!(
+                concat!(
+                    "error: Invalid command \"mvo\"\n",
+                    "  --> code.basm:10:1\n",
+                    "   |\n",
+                    "10 | mvo r1, 2\n",
+                    "   | ^^^\n",
+                    "   = help: {}\n",
+                    "   = help: did you mean `mov`?\n",
+                ),
+                help
+            )
+        );
+    }
+
+    #[test]
+    fn serializes_diagnostics() {
+        let source = "mov r0, 1\nmvo r1, 2";
+        let errors = split_tokens(source).unwrap_err();
+        let json = Diagnostic::from_error(&errors[0], "code.basm").to_json();
+        let span = json!({ "start": 10, "end": 13, "line": 2, "column": 1 });
+        let mut expected = json!({
+            "severity": "error",
+            "code": "E0003",
+            "message": "Invalid command \"mvo\"",
+            "help": errors[0].kind().help(),
+            "file": "code.basm",
+            "span": span,
+            "suggestions": [{
+                "message": "did you mean `mov`?",
+                "replacement": "mov",
+                "span": span,
+            }],
+        });
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), expected);
+
+        // Errors without a location have no code, help or span
+        let json = Diagnostic::new("Failed to read file".to_owned(), "code.basm").to_json();
+        expected = json!({
+            "severity": "error",
+            "code": null,
+            "message": "Failed to read file",
+            "help": null,
+            "file": "code.basm",
+            "span": null,
+            "suggestions": [],
+        });
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), expected);
+    }
+}
@@ -1,50 +1,318 @@
-use std::fmt::Debug;
+use super::{span::Span, DIRECTIVES, MNEMONICS};
+use crate::execute::MAX_MEMORY_SIZE;
+use serde::Serialize;
+use std::fmt::{self, Debug, Display};
 
 /// The kind of compilation error
-#[derive(Clone, Copy)]
-enum ErrorKind {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The line ended before the instruction was complete
     EndOfLine,
+
+    /// The code ended before the instruction, string, or comment was complete
     EndOfFile,
+
+    /// The command isn't a known instruction or label
+    UnknownMnemonic(String),
+
+    /// The register id isn't in the range r0 up to and including r255
+    InvalidRegister(String),
+
+    /// The argument is neither a register nor a 64-bit signed integer
+    InvalidLiteral(String),
+
+    /// The escape character isn't supported in strings
+    InvalidEscape(char),
+
+    /// The label is empty or contains characters after the ':'
+    MalformedLabel(String),
+
+    /// The operand has a different kind than the instruction expects
+    WrongOperandKind {
+        expected: &'static str,
+        found: String,
+    },
+
+    /// The character isn't allowed at this position
+    UnexpectedCharacter(char),
+
+    /// A branching instruction jumps to a label that doesn't exist
+    UndefinedLabel(String),
+
+    /// The memory address isn't a register, label and offset between brackets
+    InvalidAddress(String),
+
+    /// An instruction is used in the data section, or data outside of it
+    WrongSection {
+        found: String,
+        section: &'static str,
+    },
+
+    /// The argument of a data directive isn't a valid number or string,
+    /// or the data doesn't fit in the memory
+    InvalidData(String),
+
+    /// The command starts with '.', but isn't a known directive
+    UnknownDirective(String),
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EndOfLine => write!(f, "Unexpected End Of Line"),
+            Self::EndOfFile => write!(f, "Unexpected End Of File"),
+            Self::UnknownMnemonic(command) => write!(f, "Invalid command \"{command}\""),
+            Self::InvalidRegister(register) => write!(f, "Invalid register id \"{register}\""),
+            Self::InvalidLiteral(value) => write!(f, "Invalid argument \"{value}\""),
+            Self::InvalidEscape(c) => write!(f, "Invalid escape character '{c}'"),
+            Self::MalformedLabel(label) => write!(f, "Invalid label \"{label}\""),
+            Self::WrongOperandKind { expected, found } => {
+                write!(f, "Expected {expected}, found \"{found}\"")
+            }
+            Self::UnexpectedCharacter(c) => write!(f, "Unexpected character \"{c}\""),
+            Self::UndefinedLabel(label) => write!(f, "Label \"{label}\" doesn't exist"),
+            Self::InvalidAddress(address) => write!(f, "Invalid memory address \"{address}\""),
+            Self::WrongSection { found, section } => {
+                write!(f, "\"{found}\" can't be used in the {section} section")
+            }
+            Self::InvalidData(data) => write!(f, "Invalid data \"{data}\""),
+            Self::UnknownDirective(directive) => write!(f, "Invalid directive \"{directive}\""),
+        }
+    }
+}
+
+/// Lists the names, separated by commas and with "and" before the last one
+fn list(names: &[&str]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => (*name).to_owned(),
+        [rest @ .., last] => format!("{} and {last}", rest.join(", ")),
+    }
+}
+
+impl ErrorKind {
+    /// Returns a note helping to fix the error, if there is one
+    #[must_use]
+    pub fn help(&self) -> Option<String> {
+        let help = match self {
+            Self::EndOfLine => "all arguments of an instruction must be on the same line",
+            Self::EndOfFile => return None,
+            Self::UnknownMnemonic(_) => {
+                return Some(format!("instructions are {}", list(&MNEMONICS)))
+            }
+            Self::InvalidRegister(_) => "registers are r0..r255, or sp for r255",
+            Self::InvalidLiteral(_) => {
+                "arguments are registers (r0..r255 or sp) or 64-bit signed integers"
+            }
+            Self::InvalidEscape(_) => {
+                r#"supported escape characters are \t, \n, \r, \\, \" and \0"#
+            }
+            Self::MalformedLabel(_) => {
+                "labels are written as `name:`, where name doesn't contain ':'"
+            }
+            Self::WrongOperandKind { .. } => {
+                "the result of an instruction can only be stored in a register"
+            }
+            Self::UnexpectedCharacter(_) => "arguments are separated by ','",
+            Self::UndefinedLabel(_) => {
+                "labels are declared by writing `name:` before an instruction"
+            }
+            Self::InvalidAddress(_) => {
+                "memory addresses are written as [register], [number] or [register + number], \
+                and with a label of the data section as [table], [table + 8] or [r1 + table]"
+            }
+            Self::WrongSection { .. } => {
+                "data directives follow `.data`, instructions follow `.text`"
+            }
+            Self::InvalidData(_) => {
+                return Some(format!(
+                    ".word takes 64-bit integers, .byte integers from 0 to 255, \
+                    .ascii a string and .zero a number of bytes, \
+                    and the data section holds at most {MAX_MEMORY_SIZE} bytes"
+                ))
+            }
+            Self::UnknownDirective(_) => {
+                return Some(format!("directives are {}", list(&DIRECTIVES)))
+            }
+        };
+        Some(help.to_owned())
+    }
+}
+
+/// How serious a diagnostic is.
+/// Every diagnostic of the assembler is an error for now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+}
+
+impl ErrorKind {
+    /// Returns the unique code of the kind of error
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::EndOfLine => "E0001",
+            Self::EndOfFile => "E0002",
+            Self::UnknownMnemonic(_) => "E0003",
+            Self::InvalidRegister(_) => "E0004",
+            Self::InvalidLiteral(_) => "E0005",
+            Self::InvalidEscape(_) => "E0006",
+            Self::MalformedLabel(_) => "E0007",
+            Self::WrongOperandKind { .. } => "E0008",
+            Self::UnexpectedCharacter(_) => "E0009",
+            Self::UndefinedLabel(_) => "E0010",
+            Self::InvalidAddress(_) => "E0011",
+            Self::WrongSection { .. } => "E0012",
+            Self::InvalidData(_) => "E0013",
+            Self::UnknownDirective(_) => "E0014",
+        }
+    }
+
+    /// Returns how serious the error is
+    #[must_use]
+    pub const fn severity(&self) -> Severity {
+        Severity::Error
+    }
 }
 
 /// The actual error
-#[derive(Clone, Copy)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct Error {
     kind: ErrorKind,
-    line: usize,
-    column: usize,
+    span: Span,
+    suggestion: Option<String>,
 }
 
 impl Error {
-    /// Creates an end of line error
-    pub const fn end_of_line(line: usize, column: usize) -> Self {
+    /// Creates an error of the passed kind at the passed span
+    pub(crate) const fn new(kind: ErrorKind, span: Span) -> Self {
         Self {
-            kind: ErrorKind::EndOfLine,
-            line,
-            column,
+            kind,
+            span,
+            suggestion: None,
         }
     }
 
+    /// Adds a replacement for the code in the span of the error, that would probably fix it
+    pub(crate) fn with_suggestion(mut self, suggestion: Option<&str>) -> Self {
+        self.suggestion = suggestion.map(str::to_owned);
+        self
+    }
+
+    /// Creates an end of line error
+    pub(crate) const fn end_of_line(span: Span) -> Self {
+        Self::new(ErrorKind::EndOfLine, span)
+    }
+
     /// Creates an end of file error
-    pub const fn end_of_file(line: usize, column: usize) -> Self {
-        Self {
-            kind: ErrorKind::EndOfFile,
-            line,
-            column,
-        }
+    pub(crate) const fn end_of_file(span: Span) -> Self {
+        Self::new(ErrorKind::EndOfFile, span)
+    }
+
+    /// Returns the kind of error
+    #[must_use]
+    pub const fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Returns a replacement for the code in the span of the error, if one is known
+    #[must_use]
+    pub fn suggestion(&self) -> Option<&str> {
+        self.suggestion.as_deref()
+    }
+
+    /// Returns the part of the code causing the error
+    #[must_use]
+    pub const fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns the line the error occurred on
+    #[must_use]
+    pub const fn line(&self) -> usize {
+        self.span.line
+    }
+
+    /// Returns the column the error occurred on
+    #[must_use]
+    pub const fn column(&self) -> usize {
+        self.span.column
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}:{}", self.kind, self.line(), self.column())
     }
 }
 
 /// The output on unwrap or when printed in debug mode
 impl Debug for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.kind {
-            ErrorKind::EndOfFile => {
-                write!(f, "Unexpected End Of File: {}:{}", self.line, self.column)
-            }
-            ErrorKind::EndOfLine => {
-                write!(f, "Unexpected End Of Line: {}:{}", self.line, self.column)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Returns the candidate closest to the name, if it is a likely typo of the name
+pub(crate) fn closest<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= name.chars().count().div_ceil(3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Returns the number of characters that have to be inserted, removed, replaced,
+/// or swapped with the next character to turn the left string into the right string
+fn edit_distance(left: &str, right: &str) -> usize {
+    let left = left.chars().collect::<Vec<_>>();
+    let right = right.chars().collect::<Vec<_>>();
+
+    // Keep the distances of the last 2 rows, needed to detect swapped characters
+    let mut before = Vec::new();
+    let mut previous = (0..=right.len()).collect::<Vec<_>>();
+    for i in 0..left.len() {
+        let mut current = vec![i + 1];
+        for j in 0..right.len() {
+            let replace = previous[j] + usize::from(left[i] != right[j]);
+            let mut distance = replace.min(previous[j + 1] + 1).min(current[j] + 1);
+            if i > 0 && j > 0 && left[i] == right[j - 1] && left[i - 1] == right[j] {
+                distance = distance.min(before[j - 1] + 1);
             }
+            current.push(distance);
         }
+        before = previous;
+        previous = current;
+    }
+    previous[right.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{list, ErrorKind};
+
+    #[test]
+    fn lists_names() {
+        assert_eq!(list(&[]), "");
+        assert_eq!(list(&["a"]), "a");
+        assert_eq!(list(&["a", "b", "c"]), "a, b and c");
+    }
+
+    #[test]
+    fn lists_every_instruction_and_directive() {
+        let help = ErrorKind::UnknownMnemonic(String::new()).help().unwrap();
+        assert!(help.ends_with(", peek, load and store"), "{help}");
+        let help = ErrorKind::UnknownDirective(String::new()).help().unwrap();
+        assert_eq!(
+            help,
+            "directives are .data, .text, .word, .byte, .ascii and .zero"
+        );
     }
 }
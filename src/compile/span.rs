@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+/// A range of the code, pointing to the source of a token or error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Span {
+    /// The byte offset of the first character
+    pub start: usize,
+
+    /// The byte offset after the last character
+    pub end: usize,
+
+    /// The line of the first character, starting at 1
+    pub line: usize,
+
+    /// The column of the first character, starting at 1
+    pub column: usize,
+}
+
+impl Span {
+    /// Returns a span from the start of `self` up to the end of `other`
+    #[must_use]
+    pub const fn to(self, other: Self) -> Self {
+        Self {
+            end: other.end,
+            ..self
+        }
+    }
+
+    /// Returns a span from the start of `self` up to the passed byte offset
+    #[must_use]
+    pub const fn until(self, end: usize) -> Self {
+        Self { end, ..self }
+    }
+
+    /// Returns the number of bytes in the span
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+
+    /// Returns whether the span doesn't contain any bytes
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
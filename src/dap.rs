@@ -0,0 +1,931 @@
+use crate::{
+    debugger::line_breakpoint,
+    execute::{trace::flag_name, Status, Vm},
+    framing::{read_message, write_message},
+    Program, Token,
+};
+use serde_json::{json, Value};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, VecDeque},
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    rc::Rc,
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
+
+/// The only thread of a program
+const THREAD_ID: i64 = 1;
+
+/// The variables reference of the registers scope
+const REGISTERS_REFERENCE: i64 = 1;
+
+/// The variables reference of the flags scope
+const FLAGS_REFERENCE: i64 = 2;
+
+/// The variables reference of the data stack scope
+const STACK_REFERENCE: i64 = 3;
+
+/// The number of instructions executed between checks for new requests while running
+const SLICE_CYCLES: usize = 10_000;
+
+/// The requests handled while the program runs, other requests wait until it stopped
+const WHILE_RUNNING: [&str; 6] = [
+    "pause",
+    "continue",
+    "setBreakpoints",
+    "threads",
+    "disconnect",
+    "terminate",
+];
+
+/// A writer storing the output of the program, so it can be sent in output events
+#[derive(Clone, Default)]
+struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads the requests on another thread, so they are received while the program runs.
+/// Stops reading after the disconnect request, the end of the input or an error.
+fn read_requests(mut input: impl BufRead + Send + 'static) -> Receiver<io::Result<Value>> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        while let Some(request) = read_message(&mut input).transpose() {
+            let last = request
+                .as_ref()
+                .map_or(true, |request| request["command"] == "disconnect");
+            if sender.send(request).is_err() || last {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// What was received from the client while the program runs
+enum Poll {
+    /// A request that is handled right away
+    Request(Value),
+
+    /// Nothing to handle yet, so the program keeps running
+    Empty,
+
+    /// The client closed the input without waiting for the program to stop
+    Closed,
+}
+
+/// The connection to the client, keeping track of the sequence number of sent messages
+struct Connection<W> {
+    requests: Receiver<io::Result<Value>>,
+    /// Requests received while the program ran, which are handled after it stopped
+    deferred: VecDeque<Value>,
+    output: W,
+    seq: i64,
+}
+
+impl<W: Write> Connection<W> {
+    /// Waits for the next request, handling the deferred requests first.
+    /// Returns `None` at the end of the input.
+    fn receive(&mut self) -> io::Result<Option<Value>> {
+        if let Some(request) = self.deferred.pop_front() {
+            return Ok(Some(request));
+        }
+        self.requests.recv().ok().transpose()
+    }
+
+    /// Returns the next request that can be handled while the program runs, without waiting.
+    /// Other requests are deferred until the program stopped. Requests after a deferred request
+    /// are deferred as well to keep their order, except for pausing.
+    /// The input is only expected to end after a deferred disconnect or terminate request,
+    /// otherwise the client is gone.
+    fn poll(&mut self) -> io::Result<Poll> {
+        loop {
+            let request = match self.requests.try_recv() {
+                Ok(request) => request?,
+                Err(TryRecvError::Empty) => return Ok(Poll::Empty),
+                Err(TryRecvError::Disconnected) => {
+                    let ending = self.deferred.iter().any(|request| {
+                        matches!(
+                            request["command"].as_str(),
+                            Some("disconnect" | "terminate")
+                        )
+                    });
+                    return Ok(if ending { Poll::Empty } else { Poll::Closed });
+                }
+            };
+            let command = request["command"].as_str().unwrap_or_default();
+            if command == "pause" || self.deferred.is_empty() && WHILE_RUNNING.contains(&command) {
+                return Ok(Poll::Request(request));
+            }
+            self.deferred.push_back(request);
+        }
+    }
+
+    /// Sends a message with the next sequence number
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    /// Sends a successful response to the request
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": true,
+            "command": request["command"],
+        });
+        response["body"] = body;
+        self.send(response)
+    }
+
+    /// Sends a failed response to the request
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": false,
+            "command": request["command"],
+            "message": message,
+        }))
+    }
+
+    /// Sends an event
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        message["body"] = body;
+        self.send(message)
+    }
+}
+
+/// Why execution stopped
+enum Stop {
+    Step,
+    Breakpoint,
+    Pause,
+    Error(String),
+    Halted,
+}
+
+/// How far a running program runs, if it doesn't reach a breakpoint
+#[derive(Clone, Copy)]
+enum Run {
+    /// Until the program halts
+    Continue,
+    /// Until no more calls than the depth haven't returned
+    Depth(usize),
+}
+
+/// A debugging session of a launched program
+struct Session<'a> {
+    program: &'a Program,
+    path: PathBuf,
+    vm: Vm<'a>,
+    output: OutputBuffer,
+    breakpoints: BTreeSet<usize>,
+    /// How far the program runs, `None` if it is stopped
+    running: Option<Run>,
+}
+
+impl Session<'_> {
+    /// Executes the next instruction and skips no-op tokens after it
+    fn step(&mut self) -> Option<Stop> {
+        match self.vm.step() {
+            Ok(Status::Paused) => {
+                while self
+                    .vm
+                    .tokens()
+                    .get(self.vm.pc())
+                    .is_some_and(Token::is_no_op)
+                {
+                    if let Err(error) = self.vm.step() {
+                        return Some(Stop::Error(self.program.locate(error).to_string()));
+                    }
+                }
+                self.vm.is_halted().then_some(Stop::Halted)
+            }
+            Ok(Status::Halted) => Some(Stop::Halted),
+            Err(error) => Some(Stop::Error(self.program.locate(error).to_string())),
+        }
+    }
+
+    /// Executes at most a slice of instructions.
+    /// Returns why the program stopped, if it ran as far as requested, reached a breakpoint,
+    /// halted or failed within the slice.
+    fn run_slice(&mut self, run: Run) -> Option<Stop> {
+        for _ in 0..SLICE_CYCLES {
+            if let Some(stop) = self.step() {
+                return Some(stop);
+            }
+            if matches!(run, Run::Depth(depth) if self.vm.call_stack().len() <= depth) {
+                return Some(Stop::Step);
+            }
+            if self.breakpoints.contains(&self.vm.pc()) {
+                return Some(Stop::Breakpoint);
+            }
+        }
+        None
+    }
+
+    /// Sets the breakpoints on the lines, returns the breakpoints for the response
+    fn set_breakpoints(&mut self, lines: &[usize]) -> Vec<Value> {
+        self.breakpoints.clear();
+        lines
+            .iter()
+            .map(|line| match line_breakpoint(self.program, *line) {
+                Some(index) => {
+                    self.breakpoints.insert(index);
+                    let line = self.program.span(index).map_or(*line, |span| span.line);
+                    json!({ "verified": true, "line": line })
+                }
+                None => json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No instruction on or after this line",
+                }),
+            })
+            .collect()
+    }
+
+    /// Returns the variables of a scope
+    fn variables(&self, reference: i64) -> Vec<Value> {
+        match reference {
+            REGISTERS_REFERENCE => self
+                .vm
+                .registers()
+                .iter()
+                .enumerate()
+                .map(|(id, value)| {
+                    json!({
+                        "name": format!("r{id}"),
+                        "value": value.to_string(),
+                        "type": "i64",
+                        "variablesReference": 0,
+                    })
+                })
+                .collect(),
+            FLAGS_REFERENCE => {
+                let comparison = flag_name(self.vm.comparison());
+                vec![json!({
+                    "name": "comparison",
+                    "value": comparison,
+                    "variablesReference": 0,
+                })]
+            }
+            STACK_REFERENCE => self
+                .vm
+                .stack()
+                .iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    json!({
+                        "name": format!("[{index}]"),
+                        "value": value.to_string(),
+                        "type": "i64",
+                        "variablesReference": 0,
+                    })
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the path of the code, the file in the source map if the program is bytecode.
+    /// Relative paths are resolved, as the client may run in another directory.
+    fn source_path(&self) -> PathBuf {
+        let path = self
+            .program
+            .source_map()
+            .file()
+            .map_or_else(|| self.path.clone(), PathBuf::from);
+        std::fs::canonicalize(&path).unwrap_or(path)
+    }
+
+    /// Returns the stack frames: the next instruction, followed by the calls that haven't returned
+    fn stack_frames(&self) -> Vec<Value> {
+        let path = self.source_path();
+        let calls = self.vm.call_stack().iter().rev().map(|address| address - 1);
+        std::iter::once(self.vm.pc())
+            .chain(calls)
+            .enumerate()
+            .map(|(id, index)| {
+                let span = self.program.span(index);
+                json!({
+                    "id": id,
+                    "name": format!("instruction {index}"),
+                    "source": { "path": path },
+                    "line": span.map_or(0, |span| span.line),
+                    "column": span.map_or(0, |span| span.column),
+                })
+            })
+            .collect()
+    }
+
+    /// Sends the output the program wrote since the last call
+    fn flush_output<W: Write>(&self, connection: &mut Connection<W>) -> io::Result<()> {
+        let output = std::mem::take(&mut *self.output.0.borrow_mut());
+        if output.is_empty() {
+            return Ok(());
+        }
+        connection.event(
+            "output",
+            json!({
+                "category": "stdout",
+                "output": String::from_utf8_lossy(&output),
+            }),
+        )
+    }
+
+    /// Responds to a request for the threads, stack frames, scopes or variables,
+    /// or to set a register
+    fn inspect<W: Write>(
+        &mut self,
+        request: &Value,
+        connection: &mut Connection<W>,
+    ) -> io::Result<()> {
+        let arguments = &request["arguments"];
+        match request["command"].as_str().unwrap_or_default() {
+            "threads" => connection.respond(
+                request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+            ),
+            "stackTrace" => {
+                let frames = self.stack_frames();
+                connection.respond(
+                    request,
+                    json!({ "totalFrames": frames.len(), "stackFrames": frames }),
+                )
+            }
+            "scopes" => connection.respond(
+                request,
+                json!({ "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                    { "name": "Flags", "variablesReference": FLAGS_REFERENCE, "expensive": false },
+                    { "name": "Stack", "variablesReference": STACK_REFERENCE, "expensive": false },
+                ] }),
+            ),
+            "variables" => {
+                let reference = arguments["variablesReference"].as_i64().unwrap_or_default();
+                let variables = self.variables(reference);
+                connection.respond(request, json!({ "variables": variables }))
+            }
+            "setVariable" => {
+                let register = arguments["name"]
+                    .as_str()
+                    .and_then(|name| name.strip_prefix('r'))
+                    .and_then(|id| id.parse::<u8>().ok())
+                    .filter(|_| arguments["variablesReference"] == REGISTERS_REFERENCE);
+                let value = arguments["value"]
+                    .as_str()
+                    .and_then(|value| value.trim().parse::<i64>().ok());
+                let (Some(register), Some(value)) = (register, value) else {
+                    return connection.fail(request, "Only registers can be set to numbers");
+                };
+                self.vm.set_register(register, value);
+                connection.respond(request, json!({ "value": value.to_string() }))
+            }
+            _ => connection.fail(request, "Unsupported request"),
+        }
+    }
+
+    /// Reports why execution stopped.
+    /// Returns whether the session ended, because the program halted.
+    fn report<W: Write>(&mut self, stop: Stop, connection: &mut Connection<W>) -> io::Result<bool> {
+        self.running = None;
+        self.flush_output(connection)?;
+        let (reason, description) = match stop {
+            Stop::Step => ("step", None),
+            Stop::Breakpoint => ("breakpoint", None),
+            Stop::Pause => ("pause", None),
+            Stop::Error(error) => ("exception", Some(error)),
+            Stop::Halted => {
+                connection.event("exited", json!({ "exitCode": 0 }))?;
+                connection.event("terminated", json!({}))?;
+                return Ok(true);
+            }
+        };
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+            body["text"] = json!(description);
+        }
+        connection.event("stopped", body)?;
+        Ok(false)
+    }
+}
+
+/// Handles the messages until a program is launched.
+/// Returns the launch request and the lines of requested breakpoints,
+/// or `None` if the client disconnected.
+fn configure<W: Write>(
+    connection: &mut Connection<W>,
+    lines: &mut Vec<usize>,
+) -> io::Result<Option<Value>> {
+    while let Some(request) = connection.receive()? {
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => connection.respond(
+                &request,
+                json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsSetVariable": true,
+                }),
+            )?,
+            "setBreakpoints" => {
+                *lines = breakpoint_lines(&request);
+                let breakpoints = lines
+                    .iter()
+                    .map(|line| json!({ "verified": false, "line": line }))
+                    .collect::<Vec<_>>();
+                connection.respond(&request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "launch" => return Ok(Some(request)),
+            "disconnect" => {
+                connection.respond(&request, json!({}))?;
+                return Ok(None);
+            }
+            _ => connection.fail(&request, "No program was launched")?,
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the lines of the breakpoints in a setBreakpoints request
+fn breakpoint_lines(request: &Value) -> Vec<usize> {
+    request["arguments"]["breakpoints"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|breakpoint| breakpoint["line"].as_u64())
+        .filter_map(|line| usize::try_from(line).ok())
+        .collect()
+}
+
+/// Handles the requests of a launched program, until the client disconnects
+fn debug<W: Write>(
+    session: &mut Session,
+    connection: &mut Connection<W>,
+    stop_on_entry: bool,
+) -> io::Result<()> {
+    loop {
+        // While the program runs, execute slices of it until a request is received
+        let request = if let Some(run) = session.running {
+            match connection.poll()? {
+                Poll::Request(request) => request,
+                Poll::Empty => {
+                    match session.run_slice(run) {
+                        Some(stop) => {
+                            if session.report(stop, connection)? {
+                                break;
+                            }
+                        }
+                        None => session.flush_output(connection)?,
+                    }
+                    continue;
+                }
+                Poll::Closed => return Ok(()),
+            }
+        } else if let Some(request) = connection.receive()? {
+            request
+        } else {
+            return Ok(());
+        };
+        match request["command"].as_str().unwrap_or_default() {
+            "setBreakpoints" => {
+                let breakpoints = session.set_breakpoints(&breakpoint_lines(&request));
+                connection.respond(&request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "configurationDone" => {
+                connection.respond(&request, json!({}))?;
+                if stop_on_entry {
+                    connection.event(
+                        "stopped",
+                        json!({ "reason": "entry", "threadId": THREAD_ID }),
+                    )?;
+                } else if session.breakpoints.contains(&session.vm.pc()) {
+                    session.report(Stop::Breakpoint, connection)?;
+                } else {
+                    session.running = Some(Run::Continue);
+                }
+            }
+            "threads" | "stackTrace" | "scopes" | "variables" | "setVariable" => {
+                session.inspect(&request, connection)?;
+            }
+            "continue" => {
+                connection.respond(&request, json!({ "allThreadsContinued": true }))?;
+                session.running = Some(Run::Continue);
+            }
+            // Step over runs calls until they return, step out runs until the current call returns
+            "next" => {
+                connection.respond(&request, json!({}))?;
+                session.running = Some(Run::Depth(session.vm.call_stack().len()));
+            }
+            "stepOut" => {
+                connection.respond(&request, json!({}))?;
+                let depth = session.vm.call_stack().len().saturating_sub(1);
+                session.running = Some(Run::Depth(depth));
+            }
+            "stepIn" => {
+                connection.respond(&request, json!({}))?;
+                let stop = session.step().unwrap_or(Stop::Step);
+                if session.report(stop, connection)? {
+                    break;
+                }
+            }
+            // Pausing a stopped program does nothing
+            "pause" => {
+                connection.respond(&request, json!({}))?;
+                if session.running.is_some() {
+                    session.report(Stop::Pause, connection)?;
+                }
+            }
+            "disconnect" | "terminate" => {
+                connection.respond(&request, json!({}))?;
+                return Ok(());
+            }
+            command => connection.fail(&request, &format!("Unsupported request \"{command}\""))?,
+        }
+    }
+
+    // Wait for the client to disconnect after the program halted
+    while let Some(request) = connection.receive()? {
+        if matches!(
+            request["command"].as_str(),
+            Some("disconnect" | "terminate")
+        ) {
+            connection.respond(&request, json!({}))?;
+            break;
+        }
+        connection.fail(&request, "The program halted")?;
+    }
+    Ok(())
+}
+
+/// Runs a Debug Adapter Protocol server on the input and output
+///
+/// Requests are read from the input, responses and events are written to the output.
+/// The program runs in slices, so requests like `pause` are handled while it runs,
+/// other requests are handled after it stopped. If the input ends while the program runs,
+/// the session ends, unless a disconnect request is waiting for the program to stop.
+/// The launch request takes the path of a .basm file as `program`, or of a .basmo file
+/// with debug info, whose source map points to the code,
+/// and stops before the first instruction if `stopOnEntry` is true.
+/// The maximum number of nested calls can be passed as `stackDepth`,
+/// the maximum number of values on the data stack as `stackSize`
+/// and the number of bytes of memory as `memorySize`.
+///
+/// # Errors
+/// Returns an error if the input couldn't be read or the output couldn't be written.
+pub fn serve(input: impl BufRead + Send + 'static, output: impl Write) -> io::Result<()> {
+    let mut connection = Connection {
+        requests: read_requests(input),
+        deferred: VecDeque::new(),
+        output,
+        seq: 0,
+    };
+    let mut lines = Vec::new();
+    let Some(launch) = configure(&mut connection, &mut lines)? else {
+        return Ok(());
+    };
+
+    // Load the program
+    let arguments = &launch["arguments"];
+    let path = PathBuf::from(arguments["program"].as_str().unwrap_or_default());
+    let program = match Program::load(&path) {
+        Ok(program) => program,
+        Err(error) => return connection.fail(&launch, &error.to_string()),
+    };
+    let stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or_default();
+
+    // Start the session, capturing the output of the program
+    let output = OutputBuffer::default();
+    let mut session = Session {
+        program: &program,
+        path,
+        vm: Vm::with_output(program.tokens(), output.clone()),
+        output,
+        breakpoints: BTreeSet::new(),
+        running: None,
+    };
+    session.vm.load_data(program.data());
+    if let Some(depth) = arguments["stackDepth"]
+        .as_u64()
+        .and_then(|depth| usize::try_from(depth).ok())
+    {
+        session.vm.set_stack_depth(depth);
+    }
+    if let Some(size) = arguments["stackSize"]
+        .as_u64()
+        .and_then(|size| usize::try_from(size).ok())
+    {
+        session.vm.set_stack_size(size);
+    }
+    if let Some(size) = arguments["memorySize"]
+        .as_u64()
+        .and_then(|size| usize::try_from(size).ok())
+    {
+        session.vm.set_memory_size(size);
+    }
+    while session
+        .vm
+        .tokens()
+        .get(session.vm.pc())
+        .is_some_and(Token::is_no_op)
+    {
+        session.step();
+    }
+    session.set_breakpoints(&lines);
+    connection.respond(&launch, json!({}))?;
+    connection.event("initialized", json!({}))?;
+    debug(&mut session, &mut connection, stop_on_entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+    use crate::framing::{read_message, write_message};
+    use serde_json::{json, Value};
+    use std::{
+        io::{self, BufReader, Cursor, Read, Write},
+        sync::mpsc::{self, Receiver, Sender},
+    };
+
+    /// The input of a scripted client, which stays open after the requests
+    /// until the client stops waiting for the program
+    struct Script {
+        requests: Cursor<Vec<u8>>,
+        open: Receiver<()>,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.requests.read(buf)?;
+            if read == 0 {
+                // Fails once the client closed the input
+                let _ = self.open.recv();
+            }
+            Ok(read)
+        }
+    }
+
+    /// The output to a scripted client, which closes the input once the program terminated
+    struct Client {
+        output: Vec<u8>,
+        open: Option<Sender<()>>,
+    }
+
+    impl Write for Client {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.windows(12).any(|window| window == b"\"terminated\"") {
+                self.open = None;
+            }
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns the path of a file in the tests directory
+    fn test_file(name: &str) -> String {
+        format!("{}/tests/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    /// Returns the messages launching the program, with breakpoints on the lines,
+    /// followed by the requests
+    fn script(program: &str, lines: &[usize], requests: &[Value]) -> Vec<u8> {
+        let breakpoints = lines
+            .iter()
+            .map(|line| json!({ "line": line }))
+            .collect::<Vec<_>>();
+        let setup = [
+            json!({ "command": "initialize", "arguments": { "adapterID": "basm" } }),
+            json!({ "command": "launch", "arguments": { "program": test_file(program) } }),
+            json!({
+                "command": "setBreakpoints",
+                "arguments": { "source": { "path": program }, "breakpoints": breakpoints },
+            }),
+            json!({ "command": "configurationDone" }),
+        ];
+        let mut input = Vec::new();
+        for (seq, request) in setup.iter().chain(requests).enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            write_message(&mut input, &request).unwrap();
+        }
+        input
+    }
+
+    /// Reads the messages sent by the server
+    fn messages(mut output: &[u8]) -> Vec<Value> {
+        std::iter::from_fn(|| read_message(&mut output).unwrap()).collect()
+    }
+
+    /// Runs a session launching the program, with breakpoints on the lines,
+    /// followed by the requests. The client waits until the program terminated
+    /// or the server stopped. Returns the messages sent by the server.
+    fn session(program: &str, lines: &[usize], requests: &[Value]) -> Vec<Value> {
+        let (sender, receiver) = mpsc::channel();
+        let input = Script {
+            requests: Cursor::new(script(program, lines, requests)),
+            open: receiver,
+        };
+        let mut client = Client {
+            output: Vec::new(),
+            open: Some(sender),
+        };
+        serve(BufReader::new(input), &mut client).unwrap();
+        messages(&client.output)
+    }
+
+    /// Returns the responses to the command
+    fn responses<'a>(messages: &'a [Value], command: &str) -> Vec<&'a Value> {
+        messages
+            .iter()
+            .filter(|message| message["type"] == "response" && message["command"] == command)
+            .collect()
+    }
+
+    /// Returns the reasons of the stopped events
+    fn stops(messages: &[Value]) -> Vec<&str> {
+        messages
+            .iter()
+            .filter(|message| message["event"] == "stopped")
+            .filter_map(|message| message["body"]["reason"].as_str())
+            .collect()
+    }
+
+    /// Returns the output of the program sent in output events
+    fn output(messages: &[Value]) -> String {
+        messages
+            .iter()
+            .filter(|message| message["event"] == "output")
+            .filter_map(|message| message["body"]["output"].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn runs_to_the_end() {
+        let messages = session("output.basm", &[], &[]);
+        assert!(responses(&messages, "launch")[0]["success"] == true);
+        assert_eq!(output(&messages), "Hello \"world\"\n");
+        assert!(messages
+            .iter()
+            .any(|message| message["event"] == "terminated"));
+        assert!(stops(&messages).is_empty());
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_steps_out() {
+        let messages = session(
+            "branching/call.basm",
+            &[16],
+            &[
+                json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+                json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+                json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+                json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+                json!({ "command": "next", "arguments": { "threadId": 1 } }),
+                json!({
+                    "command": "setBreakpoints",
+                    "arguments": { "source": { "path": "call.basm" }, "breakpoints": [] },
+                }),
+                json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            ],
+        );
+        assert_eq!(stops(&messages), ["breakpoint", "step", "step"]);
+
+        // The breakpoint is reached in the first call, returning to the out instruction
+        let traces = responses(&messages, "stackTrace");
+        let frames = &traces[0]["body"]["stackFrames"];
+        assert_eq!(frames.as_array().unwrap().len(), 2);
+        assert_eq!(frames[0]["line"], 16);
+        assert_eq!(frames[1]["line"], 5);
+        assert_eq!(traces[1]["body"]["stackFrames"][0]["line"], 6);
+        let variables = &responses(&messages, "variables")[0]["body"]["variables"];
+        assert_eq!(variables[1]["value"], "1");
+
+        assert!(output(&messages).ends_with("10! = 3628800\n"));
+        assert!(messages.iter().any(|message| message["event"] == "exited"));
+    }
+
+    #[test]
+    fn pauses_a_program_that_never_halts() {
+        let messages = session(
+            "branching/infinite.basm",
+            &[],
+            &[
+                json!({ "command": "pause", "arguments": { "threadId": 1 } }),
+                json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+                json!({ "command": "pause", "arguments": { "threadId": 1 } }),
+                json!({ "command": "disconnect" }),
+            ],
+        );
+
+        // Pausing the stopped program again doesn't send a stopped event
+        assert_eq!(stops(&messages), ["pause"]);
+        assert_eq!(responses(&messages, "pause").len(), 2);
+        let frame = &responses(&messages, "stackTrace")[0]["body"]["stackFrames"][0];
+        assert!((2..=6).contains(&frame["line"].as_u64().unwrap()));
+        assert!(responses(&messages, "disconnect")[0]["success"] == true);
+    }
+
+    #[test]
+    fn defers_requests_while_running() {
+        let messages = session(
+            "branching/infinite.basm",
+            &[],
+            &[
+                json!({
+                    "command": "setBreakpoints",
+                    "arguments": { "source": { "path": "infinite.basm" }, "breakpoints": [{ "line": 6 }] },
+                }),
+                json!({ "command": "variables", "arguments": { "variablesReference": 2 } }),
+                json!({ "command": "disconnect" }),
+            ],
+        );
+
+        // The variables request waits until the breakpoint is reached
+        assert_eq!(stops(&messages), ["breakpoint"]);
+        let breakpoints = &responses(&messages, "setBreakpoints")[1]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], true);
+        let stopped = messages
+            .iter()
+            .position(|message| message["event"] == "stopped")
+            .unwrap();
+        let variables = messages
+            .iter()
+            .position(|message| message["command"] == "variables")
+            .unwrap();
+        assert!(stopped < variables);
+        assert_eq!(
+            messages[variables]["body"]["variables"][0]["name"],
+            "comparison"
+        );
+    }
+
+    #[test]
+    fn disconnects_while_running() {
+        let messages = session(
+            "branching/infinite.basm",
+            &[],
+            &[json!({ "command": "disconnect" })],
+        );
+        assert!(stops(&messages).is_empty());
+        assert!(responses(&messages, "disconnect")[0]["success"] == true);
+    }
+
+    #[test]
+    fn ends_the_session_when_the_client_exits() {
+        let input = script(
+            "branching/infinite.basm",
+            &[],
+            &[json!({ "command": "threads" })],
+        );
+        let mut output = Vec::new();
+        serve(Cursor::new(input), &mut output).unwrap();
+        let messages = messages(&output);
+        assert_eq!(responses(&messages, "threads").len(), 1);
+        assert!(!messages
+            .iter()
+            .any(|message| message["event"] == "terminated"));
+    }
+
+    #[test]
+    fn rejects_invalid_variables() {
+        let messages = session(
+            "branching/call.basm",
+            &[4],
+            &[
+                json!({
+                    "command": "setVariable",
+                    "arguments": { "variablesReference": 1, "name": "r1", "value": "one" },
+                }),
+                json!({
+                    "command": "setVariable",
+                    "arguments": { "variablesReference": 1, "name": "r1", "value": "7" },
+                }),
+                json!({ "command": "disconnect" }),
+            ],
+        );
+        let responses = responses(&messages, "setVariable");
+        assert_eq!(responses[0]["success"], false);
+        assert_eq!(responses[1]["body"]["value"], "7");
+    }
+}
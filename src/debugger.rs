@@ -0,0 +1,430 @@
+use crate::{
+    execute::{trace::flag_name, Status, Vm},
+    value::STACK_POINTER,
+    Program, Token,
+};
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+};
+
+/// The number of lines printed before and after the current line by `list`
+const LIST_CONTEXT: usize = 3;
+
+/// The help text of the debugger
+const HELP: &str = "\
+Commands:
+  break <label|line>  b    Stop before executing the label or line
+  delete <label|line> d    Remove a breakpoint
+  breakpoints              List the breakpoints
+  step [count]        s    Execute the next instruction(s)
+  next                n    Execute the next instruction, running calls until they return
+  continue            c    Run until a breakpoint is reached or the program halts
+  print [register]    p    Print a register, or all registers that aren't 0
+  set <register> <value>   Set the value of a register
+  flag                f    Print the comparison flag
+  list                l    List the code around the next instruction
+  backtrace           bt   List the calls that haven't returned yet
+  stack                    List the values on the data stack, the top first
+  memory <addr> [n]   m    Print n words in memory, starting at the address
+  help                h    Print this help
+  quit                q    Stop debugging
+";
+
+/// Returns the index of the first instruction executed after jumping to the label
+#[must_use]
+pub fn label_breakpoint(program: &Program, label: &str) -> Option<usize> {
+    let tokens = program.tokens();
+    (program.label(label)?..=tokens.len())
+        .find(|index| !tokens.get(*index).is_some_and(Token::is_no_op))
+}
+
+/// Returns the index of the first instruction on the line or the first instruction after it.
+/// Only works for programs compiled from code or loaded with debug info.
+#[must_use]
+pub fn line_breakpoint(program: &Program, line: usize) -> Option<usize> {
+    (0..program.tokens().len()).find(|index| {
+        !program.tokens()[*index].is_no_op()
+            && program.span(*index).is_some_and(|span| span.line >= line)
+    })
+}
+
+/// Parses a register name like `r12`, or `sp` for the stack pointer
+fn parse_register(register: &str) -> Option<u8> {
+    if register == "sp" {
+        return Some(STACK_POINTER);
+    }
+    register.strip_prefix('r')?.parse().ok()
+}
+
+/// An interactive debugger, reading commands from the input and writing results to the output.
+/// The output of the program itself is written to stdout.
+pub struct Debugger<'a> {
+    program: &'a Program,
+    source: Option<&'a str>,
+    vm: Vm<'a>,
+    breakpoints: BTreeSet<usize>,
+}
+
+impl<'a> Debugger<'a> {
+    /// Creates a debugger for the program.
+    /// The source code is used to list code and set breakpoints on lines.
+    #[must_use]
+    pub fn new(program: &'a Program, source: Option<&'a str>) -> Self {
+        Self {
+            program,
+            source,
+            vm: program.vm(),
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Sets the maximum number of nested calls of the program
+    #[must_use]
+    pub const fn with_stack_depth(mut self, depth: usize) -> Self {
+        self.vm.set_stack_depth(depth);
+        self
+    }
+
+    /// Sets the maximum number of values on the data stack of the program
+    #[must_use]
+    pub fn with_stack_size(mut self, size: usize) -> Self {
+        self.vm.set_stack_size(size);
+        self
+    }
+
+    /// Sets the number of bytes of memory of the program
+    #[must_use]
+    pub fn with_memory_size(mut self, size: usize) -> Self {
+        self.vm.set_memory_size(size);
+        self
+    }
+
+    /// Returns the virtual machine executing the program
+    #[must_use]
+    pub const fn vm(&self) -> &Vm<'a> {
+        &self.vm
+    }
+
+    /// Returns the breakpoints, as indices of tokens
+    #[must_use]
+    pub const fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    /// Returns the index of the token a label or line refers to
+    fn resolve(&self, location: &str) -> Result<usize, String> {
+        if let Ok(line) = location.parse::<usize>() {
+            if !self.program.has_spans() {
+                return Err(
+                    "Breakpoints on lines require the code or debug info of the program".to_owned(),
+                );
+            }
+            line_breakpoint(self.program, line)
+                .ok_or_else(|| format!("No instruction on or after line {line}"))
+        } else {
+            label_breakpoint(self.program, location)
+                .ok_or_else(|| format!("Label \"{location}\" doesn't exist"))
+        }
+    }
+
+    /// Returns the line of code containing the token, if the location of the token is known
+    fn line_of(&self, index: usize) -> Option<usize> {
+        self.program.span(index).map(|span| span.line)
+    }
+
+    /// Executes no-op tokens, so the next token is an instruction
+    fn skip_no_ops(&mut self) {
+        while self
+            .vm
+            .tokens()
+            .get(self.vm.pc())
+            .is_some_and(Token::is_no_op)
+        {
+            if self.vm.step().is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Executes the next instruction, returns whether the program can continue
+    fn step(&mut self, output: &mut impl Write) -> io::Result<bool> {
+        match self.vm.step() {
+            Ok(Status::Paused) => {
+                self.skip_no_ops();
+                Ok(!self.vm.is_halted())
+            }
+            Ok(Status::Halted) => Ok(false),
+            Err(error) => {
+                writeln!(output, "error: {}", self.program.locate(error))?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Prints where the program stopped
+    fn print_location(&self, output: &mut impl Write) -> io::Result<()> {
+        let pc = self.vm.pc();
+        let Some(token) = self.vm.tokens().get(pc) else {
+            return writeln!(output, "Program halted after {} cycles", self.vm.cycles());
+        };
+        match (self.line_of(pc), self.source) {
+            (Some(line), Some(source)) => {
+                let code = source.lines().nth(line - 1).unwrap_or_default().trim();
+                writeln!(output, "Stopped at instruction {pc}, line {line}: {code}")
+            }
+            (Some(line), None) => {
+                writeln!(output, "Stopped at instruction {pc}, line {line}: {token}")
+            }
+            _ => writeln!(output, "Stopped at instruction {pc}: {token}"),
+        }
+    }
+
+    /// Prints the code around the next instruction
+    fn list(&self, output: &mut impl Write) -> io::Result<()> {
+        let pc = self.vm.pc();
+        let (Some(source), Some(current)) = (self.source, self.line_of(pc)) else {
+            // List the tokens if the code isn't known
+            let start = pc.saturating_sub(LIST_CONTEXT);
+            for (index, token) in self.vm.tokens().iter().enumerate().skip(start) {
+                if index > pc + LIST_CONTEXT {
+                    break;
+                }
+                let marker = if index == pc { "->" } else { "  " };
+                writeln!(output, "{marker} {index:>4} {token}")?;
+            }
+            return Ok(());
+        };
+        let start = current.saturating_sub(LIST_CONTEXT).max(1);
+        for (line, code) in source.lines().enumerate().skip(start - 1) {
+            let line = line + 1;
+            if line > current + LIST_CONTEXT {
+                break;
+            }
+            let marker = if line == current { "->" } else { "  " };
+            writeln!(output, "{marker} {line:>4} {code}")?;
+        }
+        Ok(())
+    }
+
+    /// Prints the next instruction, followed by the calls that haven't returned
+    fn backtrace(&self, output: &mut impl Write) -> io::Result<()> {
+        // The calls are the instructions before the return addresses
+        let calls = self.vm.call_stack().iter().rev().map(|address| address - 1);
+        for (frame, index) in std::iter::once(self.vm.pc()).chain(calls).enumerate() {
+            match self.line_of(index) {
+                Some(line) => writeln!(output, "#{frame} instruction {index}, line {line}")?,
+                None => writeln!(output, "#{frame} instruction {index}")?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints the value of a register, or all registers that aren't 0
+    fn print(&self, register: Option<&str>, output: &mut impl Write) -> io::Result<()> {
+        let registers = self.vm.registers();
+        let Some(register) = register else {
+            for (id, value) in registers
+                .iter()
+                .enumerate()
+                .filter(|(_, value)| **value != 0)
+            {
+                writeln!(output, "r{id} = {value}")?;
+            }
+            return Ok(());
+        };
+        match parse_register(register) {
+            Some(id) => writeln!(output, "r{id} = {}", registers[id as usize]),
+            None => writeln!(output, "Invalid register \"{register}\""),
+        }
+    }
+
+    /// Prints the words in memory starting at the address
+    fn memory(
+        &self,
+        address: Option<&str>,
+        count: Option<&str>,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        let (Some(Ok(address)), Ok(count)) = (
+            address.map(str::parse::<i64>),
+            count.map_or(Ok(1), str::parse::<i64>),
+        ) else {
+            return writeln!(output, "Usage: memory <address> [count]");
+        };
+        for address in (0..count).map(|index| address.saturating_add(index.saturating_mul(8))) {
+            match self.vm.read_word(address) {
+                Some(value) => writeln!(output, "[{address}] = {value}")?,
+                None => return writeln!(output, "Address {address} is outside the memory"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes a single command, returns whether the debugger should continue
+    ///
+    /// # Errors
+    /// Returns an error if the output couldn't be written.
+    pub fn command(&mut self, line: &str, output: &mut impl Write) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+        let argument = words.next();
+        match (command, argument) {
+            ("break" | "b", Some(location)) => match self.resolve(location) {
+                Ok(index) => {
+                    self.breakpoints.insert(index);
+                    writeln!(output, "Breakpoint set at instruction {index}")?;
+                }
+                Err(error) => writeln!(output, "{error}")?,
+            },
+            ("delete" | "d", Some(location)) => match self.resolve(location) {
+                Ok(index) if self.breakpoints.remove(&index) => {
+                    writeln!(output, "Breakpoint at instruction {index} removed")?;
+                }
+                Ok(index) => writeln!(output, "No breakpoint at instruction {index}")?,
+                Err(error) => writeln!(output, "{error}")?,
+            },
+            ("breakpoints", None) => {
+                for index in &self.breakpoints {
+                    match self.line_of(*index) {
+                        Some(line) => writeln!(output, "Instruction {index}, line {line}")?,
+                        None => writeln!(output, "Instruction {index}")?,
+                    }
+                }
+            }
+            ("step" | "s", count) => {
+                let Ok(count) = count.map_or(Ok(1), str::parse::<usize>) else {
+                    writeln!(output, "Invalid number of steps")?;
+                    return Ok(true);
+                };
+                // Stop early when reaching a breakpoint, like continue does
+                for _ in 0..count {
+                    if !self.step(output)? || self.breakpoints.contains(&self.vm.pc()) {
+                        break;
+                    }
+                }
+                self.print_location(output)?;
+            }
+            ("next" | "n", None) => {
+                let depth = self.vm.call_stack().len();
+                if self.step(output)? {
+                    while self.vm.call_stack().len() > depth
+                        && !self.breakpoints.contains(&self.vm.pc())
+                        && self.step(output)?
+                    {}
+                }
+                self.print_location(output)?;
+            }
+            ("continue" | "c", None) => {
+                while self.step(output)? && !self.breakpoints.contains(&self.vm.pc()) {}
+                self.print_location(output)?;
+            }
+            ("print" | "p", register) => self.print(register, output)?,
+            ("set", Some(register)) => {
+                match (
+                    parse_register(register),
+                    words.next().map(str::parse::<i64>),
+                ) {
+                    (Some(id), Some(Ok(value))) => {
+                        self.vm.set_register(id, value);
+                        writeln!(output, "r{id} = {value}")?;
+                    }
+                    _ => writeln!(output, "Usage: set <register> <value>")?,
+                }
+            }
+            ("flag" | "f", None) => {
+                let flag = flag_name(self.vm.comparison());
+                writeln!(output, "comparison = {flag}")?;
+            }
+            ("list" | "l", None) => self.list(output)?,
+            ("backtrace" | "bt", None) => self.backtrace(output)?,
+            ("memory" | "m", address) => self.memory(address, words.next(), output)?,
+            ("stack", None) => {
+                // Offsets are relative to the top, as used by peek
+                for (offset, value) in self.vm.stack().iter().rev().enumerate() {
+                    writeln!(output, "{offset:>4}: {value}")?;
+                }
+            }
+            ("help" | "h", None) => write!(output, "{HELP}")?,
+            ("quit" | "q", None) => return Ok(false),
+            _ => writeln!(output, "Invalid command \"{line}\", type help for help")?,
+        }
+        Ok(true)
+    }
+
+    /// Reads commands from the input until the input ends or the debugger is quit
+    ///
+    /// # Errors
+    /// Returns an error if the input couldn't be read or the output couldn't be written.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        self.skip_no_ops();
+        self.print_location(&mut output)?;
+        write!(output, "(basm) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            if !self.command(&line?, &mut output)? {
+                break;
+            }
+            write!(output, "(basm) ")?;
+            output.flush()?;
+        }
+        writeln!(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use crate::Program;
+
+    /// Debugs the code, feeding the commands to the debugger and returning its output
+    fn debug(code: &str, commands: &str) -> String {
+        let program = Program::assemble(code).unwrap();
+        let mut output = Vec::new();
+        Debugger::new(&program, Some(code))
+            .run(commands.as_bytes(), &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn stops_stepping_at_breakpoints() {
+        let code = "mov r0, 1\nmov r1, 2\nmov r2, 3\nmov r3, 4";
+        let output = debug(code, "break 3\nstep 3\np r1\np r2\nstep 5\n");
+        assert_eq!(
+            output,
+            "Stopped at instruction 0, line 1: mov r0, 1\n\
+            (basm) Breakpoint set at instruction 2\n\
+            (basm) Stopped at instruction 2, line 3: mov r2, 3\n\
+            (basm) r1 = 2\n\
+            (basm) r2 = 0\n\
+            (basm) Program halted after 4 cycles\n\
+            (basm) \n"
+        );
+    }
+
+    #[test]
+    fn runs_scripted_commands() {
+        let code = "mov r0, 5\ncall double\nmov r2, r1\njmp end\n\
+            double:\n    add r1, r0, r0\n    ret\nend:\nmov r3, 1";
+        let commands = "b double\nc\nbt\nset r0 7\ns 2\nn\np\nc\nq\nstep\n";
+        assert_eq!(
+            debug(code, commands),
+            "Stopped at instruction 0, line 1: mov r0, 5\n\
+            (basm) Breakpoint set at instruction 5\n\
+            (basm) Stopped at instruction 5, line 6: add r1, r0, r0\n\
+            (basm) #0 instruction 5, line 6\n\
+            #1 instruction 1, line 2\n\
+            (basm) r0 = 7\n\
+            (basm) Stopped at instruction 2, line 3: mov r2, r1\n\
+            (basm) Stopped at instruction 3, line 4: jmp end\n\
+            (basm) r0 = 7\n\
+            r1 = 14\n\
+            r2 = 14\n\
+            (basm) Program halted after 7 cycles\n\
+            (basm) \n"
+        );
+    }
+}
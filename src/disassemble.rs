@@ -0,0 +1,205 @@
+use crate::{
+    execute::WORD_SIZE,
+    token::{directive::Directive, Label},
+    Program, Token,
+};
+use std::{
+    collections::HashSet,
+    fmt::{self, Write as _},
+};
+
+/// The address jumped to by the token, if it is a jump to an address
+const fn jump_address(token: &Token) -> Option<usize> {
+    match token {
+        Token::Jmp(Label::Address(address))
+        | Token::Jl(Label::Address(address))
+        | Token::Jg(Label::Address(address))
+        | Token::Je(Label::Address(address))
+        | Token::Call(Label::Address(address)) => Some(*address),
+        _ => None,
+    }
+}
+
+/// Names the addresses jumped to.
+/// Addresses after a label token keep the name of the label, other addresses get the name of
+/// a label in the debug info, or a generated name if they are jumped to.
+///
+/// Returns the name of every address, and the labels to add before every address.
+fn name_addresses(program: &Program) -> (Vec<Option<String>>, Vec<Vec<String>>) {
+    let tokens = program.tokens();
+    let mut names = vec![None; tokens.len() + 1];
+    for (index, token) in tokens.iter().enumerate() {
+        if let Token::Label(name) = token {
+            names[index + 1] = Some(name.clone());
+        }
+    }
+    let mut used = names.iter().flatten().cloned().collect::<HashSet<_>>();
+    let mut added = vec![Vec::new(); tokens.len() + 1];
+
+    // Add the labels removed when the program was stripped
+    for (name, address) in program.labels() {
+        if *address > tokens.len() || !used.insert(name.clone()) {
+            continue;
+        }
+        names[*address].get_or_insert_with(|| name.clone());
+        added[*address].push(name.clone());
+    }
+
+    // Generate unique names for the other addresses that are jumped to
+    for address in tokens.iter().filter_map(jump_address) {
+        let Some(name @ None) = names.get_mut(address) else {
+            continue;
+        };
+        let mut generated = format!("label_{address}");
+        while used.contains(&generated) {
+            generated.push('_');
+        }
+        used.insert(generated.clone());
+        added[address].push(generated.clone());
+        *name = Some(generated);
+    }
+    (names, added)
+}
+
+/// Returns the name of every address jumped to, or following a label
+pub(crate) fn address_names(program: &Program) -> Vec<Option<String>> {
+    name_addresses(program).0
+}
+
+/// Writes the token, with jumps to addresses written as jumps to the name of the address
+pub(crate) fn write_token(
+    f: &mut impl fmt::Write,
+    token: &Token,
+    names: &[Option<String>],
+) -> fmt::Result {
+    let name = |label: &Label| match label {
+        Label::Address(address) => names
+            .get(*address)
+            .cloned()
+            .flatten()
+            .map_or_else(|| label.clone(), Label::Base),
+        Label::Base(_) => label.clone(),
+    };
+    match token {
+        Token::Jmp(label) => write!(f, "{}", Token::Jmp(name(label))),
+        Token::Jl(label) => write!(f, "{}", Token::Jl(name(label))),
+        Token::Jg(label) => write!(f, "{}", Token::Jg(name(label))),
+        Token::Je(label) => write!(f, "{}", Token::Je(name(label))),
+        Token::Call(label) => write!(f, "{}", Token::Call(name(label))),
+        token => write!(f, "{token}"),
+    }
+}
+
+/// The number of words written on a line of the data section
+const WORDS_PER_LINE: usize = 4;
+
+/// Turns a program back into code, which assembles to the same tokens.
+/// The data section is written after the instructions, as words and the remaining bytes.
+///
+/// Jumps to addresses are written as jumps to the label before that address.
+/// If there is no label before a jumped to address, the label is taken from the debug info
+/// or generated, so the program behaves the same but the bytecode of the reassembled code differs.
+///
+/// # Panics
+/// Never panics, as formatting into a string can't fail.
+#[must_use]
+pub fn disassemble(program: &Program) -> String {
+    let (names, added) = name_addresses(program);
+
+    let mut code = String::new();
+    for (index, token) in program.tokens().iter().enumerate() {
+        // Add the labels before the token they refer to
+        for label in &added[index] {
+            writeln!(code, "{label}:").unwrap();
+        }
+
+        // Write the token, labels start at the beginning of the line
+        if !matches!(token, Token::Label(_)) {
+            code.push_str("    ");
+        }
+        write_token(&mut code, token, &names).unwrap();
+        code.push('\n');
+    }
+    for label in &added[program.tokens().len()] {
+        writeln!(code, "{label}:").unwrap();
+    }
+
+    let data = program.data();
+    if !data.is_empty() {
+        code.push_str("\n.data\n");
+        let words = data.chunks_exact(WORD_SIZE);
+        let bytes = words.remainder().to_vec();
+        let words = words
+            .map(|word| i64::from_le_bytes(word.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        for line in words.chunks(WORDS_PER_LINE) {
+            writeln!(code, "    {}", Directive::Word(line.to_vec())).unwrap();
+        }
+        if !bytes.is_empty() {
+            writeln!(code, "    {}", Directive::Byte(bytes)).unwrap();
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+    use crate::{program::tests::samples, token::Label, Program, Token};
+
+    #[test]
+    fn reassembles_to_the_same_bytecode() {
+        for (path, program) in samples() {
+            let code = disassemble(&program);
+            let reassembled = Program::assemble(&code).unwrap();
+            assert_eq!(reassembled.tokens(), program.tokens(), "{}", path.display());
+            assert_eq!(reassembled.data(), program.data(), "{}", path.display());
+            assert_eq!(
+                reassembled.to_bytes().unwrap(),
+                program.to_bytes().unwrap(),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn reassembles_stripped_programs() {
+        for (path, program) in samples() {
+            let stripped = program.strip();
+            let reassembled = Program::assemble(&disassemble(&stripped)).unwrap().strip();
+            assert_eq!(
+                reassembled.to_bytes().unwrap(),
+                stripped.to_bytes().unwrap(),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn generates_unique_label_names() {
+        let program = Program::from_tokens(vec![
+            Token::Label("label_2".to_owned()),
+            Token::Ret,
+            Token::Ret,
+            Token::Jmp(Label::Address(2)),
+            Token::Jmp(Label::Address(5)),
+        ]);
+        assert_eq!(
+            disassemble(&program),
+            "label_2:\n    ret\nlabel_2_:\n    ret\n    jmp label_2_\n    jmp label_5\nlabel_5:\n"
+        );
+    }
+
+    #[test]
+    fn writes_the_data_section() {
+        let program = Program::assemble(".data\n.word 1, -1\n.byte 7\n.text\nmov r0, 1\n").unwrap();
+        let code = disassemble(&program);
+        assert_eq!(
+            code,
+            "    mov r0, 1\n\n.data\n    .word 1, -1\n    .byte 7\n"
+        );
+        assert_eq!(Program::assemble(&code).unwrap().data(), program.data());
+    }
+}
@@ -1,13 +1,45 @@
-use crate::{token::Label, Token};
+use crate::{
+    token::Label,
+    value::{Value, STACK_POINTER},
+    Token,
+};
+use error::{RuntimeError, RuntimeErrorKind};
 use std::{
     cmp::Ordering,
     collections::HashMap,
-    fmt::Write as _,
-    io::{self, Write as _},
+    fmt::{self, Display, Write as _},
+    io,
+    ops::Range,
 };
+use trace::Tracer;
+
+pub mod error;
+pub mod snapshot;
+pub mod trace;
+
+/// The maximum number of nested calls, unless another depth is set
+pub const DEFAULT_STACK_DEPTH: usize = 1024;
+
+/// The number of values the data stack holds, unless another size is set
+pub const DEFAULT_STACK_SIZE: usize = 65536;
+
+/// The number of bytes of memory, unless another size is set
+pub const DEFAULT_MEMORY_SIZE: usize = 65536;
+
+/// The maximum number of bytes of memory, which also limits the size of the data section
+pub const MAX_MEMORY_SIZE: usize = 1 << 24;
 
-/// Executes the output command
-pub fn out(output: &str, registers: &[i64]) {
+/// The number of bytes loaded and stored by memory instructions
+pub const WORD_SIZE: usize = 8;
+
+/// Executes the output command, writing the output to the writer
+///
+/// # Errors
+/// Returns an error if the output couldn't be written.
+///
+/// # Panics
+/// Never panics, as formatting into a string can't fail.
+pub fn out(output: &str, registers: &[i64], writer: &mut dyn io::Write) -> io::Result<()> {
     // Create a string for the result and registers
     let mut result = String::new();
     let mut register = String::new();
@@ -48,8 +80,8 @@ pub fn out(output: &str, registers: &[i64]) {
         }
     }
 
-    // Print the output ot the screen
-    io::stdout().lock().write_all(result.as_bytes()).unwrap();
+    // Write the output
+    writer.write_all(result.as_bytes())
 }
 
 fn read_labels(tokens: &[Token]) -> HashMap<String, usize> {
@@ -66,74 +98,790 @@ fn read_labels(tokens: &[Token]) -> HashMap<String, usize> {
         .collect::<HashMap<_, _>>()
 }
 
-fn label_to_address(label: &Label, labels: &HashMap<String, usize>) -> usize {
+/// Returns the address execution continues at after jumping to the label.
+/// Jumping to the end of the program halts it.
+/// Errors, if the label doesn't exist or the address is outside of the program
+fn label_to_address(
+    label: &Label,
+    labels: &HashMap<String, usize>,
+    tokens: &[Token],
+    index: usize,
+) -> Result<usize, RuntimeError> {
+    let invalid =
+        |target: String| RuntimeError::new(RuntimeErrorKind::InvalidJumpTarget(target), index);
     match label {
-        Label::Base(label) => *labels.get(label).unwrap(),
-        Label::Address(address) => *address,
+        Label::Base(label) => labels
+            .get(label)
+            .map(|address| address + 1)
+            .ok_or_else(|| invalid(label.clone())),
+        Label::Address(address) if *address <= tokens.len() => Ok(*address),
+        Label::Address(address) => Err(invalid(address.to_string())),
     }
 }
 
-/// Executes the tokens
-pub fn execute(tokens: &[Token], mut cycles: Option<usize>) {
-    // Create and initialize the registers
-    let mut registers = [0i64; 256];
-    let labels = read_labels(tokens);
-    let mut index = 0;
-    let mut comparison = Ordering::Equal;
-
-    // Iterate through the tokens
-    while let Some(token) = tokens.get(index) {
-        // Stop, if the number cycles to execute reached 0
-        if cycles.is_some_and(|cycles| cycles == 0) {
-            break;
+/// Returns the number of bits to shift or rotate by, the amount modulo 64
+fn shift_amount(amount: i64) -> u32 {
+    u32::try_from(amount.rem_euclid(64)).unwrap_or_default()
+}
+
+/// Shifts the bits of the value to the left, filling with zeroes
+fn shift_left(value: i64, amount: i64) -> i64 {
+    value << shift_amount(amount)
+}
+
+/// Shifts the bits of the value to the right, filling with zeroes
+fn shift_right(value: i64, amount: i64) -> i64 {
+    (value.cast_unsigned() >> shift_amount(amount)).cast_signed()
+}
+
+/// Shifts the bits of the value to the right, filling with the sign bit
+fn shift_arithmetic(value: i64, amount: i64) -> i64 {
+    value >> shift_amount(amount)
+}
+
+/// Rotates the bits of the value to the left
+fn rotate_left(value: i64, amount: i64) -> i64 {
+    value.rotate_left(shift_amount(amount))
+}
+
+/// Rotates the bits of the value to the right
+fn rotate_right(value: i64, amount: i64) -> i64 {
+    value.rotate_right(shift_amount(amount))
+}
+
+/// Performs a division or remainder operation, erroring if the right operand is 0
+fn divide(
+    left: &Value,
+    right: &Value,
+    registers: &[i64],
+    index: usize,
+    operation: impl FnOnce(i64, i64) -> i64,
+) -> Result<i64, RuntimeError> {
+    if right.take(registers) == 0 {
+        return Err(RuntimeError::new(RuntimeErrorKind::DivisionByZero, index));
+    }
+    Ok(left.perform_operation(right, registers, operation))
+}
+
+/// Calculates the result of an instruction storing a calculation in a register.
+/// Returns the register and the result, or `None` if the token isn't a calculation.
+fn calculate(
+    token: &Token,
+    registers: &[i64],
+    index: usize,
+) -> Result<Option<(u8, i64)>, RuntimeError> {
+    let (id, result) = match token {
+        Token::Add(id, left, right) => (
+            id,
+            left.perform_operation(right, registers, i64::wrapping_add),
+        ),
+        Token::Sub(id, left, right) => (
+            id,
+            left.perform_operation(right, registers, i64::wrapping_sub),
+        ),
+        Token::Mul(id, left, right) => (
+            id,
+            left.perform_operation(right, registers, i64::wrapping_mul),
+        ),
+        Token::Div(id, left, right) => (
+            id,
+            divide(left, right, registers, index, i64::wrapping_div)?,
+        ),
+        Token::Mod(id, left, right) => (
+            id,
+            divide(left, right, registers, index, i64::wrapping_rem)?,
+        ),
+        Token::And(id, left, right) => (id, left.perform_operation(right, registers, |a, b| a & b)),
+        Token::Or(id, left, right) => (id, left.perform_operation(right, registers, |a, b| a | b)),
+        Token::Xor(id, left, right) => (id, left.perform_operation(right, registers, |a, b| a ^ b)),
+        Token::Not(id, value) => (id, !value.take(registers)),
+        Token::Shl(id, left, right) => (id, left.perform_operation(right, registers, shift_left)),
+        Token::Shr(id, left, right) => (id, left.perform_operation(right, registers, shift_right)),
+        Token::Sar(id, left, right) => (
+            id,
+            left.perform_operation(right, registers, shift_arithmetic),
+        ),
+        Token::Rol(id, left, right) => (id, left.perform_operation(right, registers, rotate_left)),
+        Token::Ror(id, left, right) => (id, left.perform_operation(right, registers, rotate_right)),
+        _ => return Ok(None),
+    };
+    Ok(Some((*id, result)))
+}
+
+/// The state of a program that halted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitState {
+    /// The number of executed cycles
+    pub cycles: usize,
+}
+
+impl Display for ExitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "halted after {} cycles", self.cycles)
+    }
+}
+
+/// Whether the virtual machine can continue executing the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The program reached its end
+    Halted,
+
+    /// The program stopped before reaching its end, and can be resumed
+    Paused,
+}
+
+/// A virtual machine executing a program.
+/// Holds the registers, comparison flag, program counter, the return addresses of calls,
+/// the data stack and memory, so execution can be paused and resumed.
+///
+/// Memory is addressed in bytes, load and store access 8-byte little endian words.
+///
+/// The data stack grows upwards from 0. The stack pointer register `sp`, which is r255,
+/// holds the number of values on the stack and can be changed like any other register.
+pub struct Vm<'a> {
+    tokens: &'a [Token],
+    labels: HashMap<String, usize>,
+    registers: [i64; 256],
+    comparison: Ordering,
+    pc: usize,
+    cycles: usize,
+    call_stack: Vec<usize>,
+    stack_depth: usize,
+    stack: Vec<i64>,
+    memory: Vec<u8>,
+    data: &'a [u8],
+    output_position: usize,
+    output: Box<dyn io::Write + 'a>,
+    tracer: Option<Tracer<'a>>,
+}
+
+impl<'a> Vm<'a> {
+    /// Creates a virtual machine executing the tokens, writing output to stdout
+    #[must_use]
+    pub fn new(tokens: &'a [Token]) -> Self {
+        Self::with_output(tokens, io::stdout())
+    }
+
+    /// Creates a virtual machine executing the tokens, writing output to the writer
+    #[must_use]
+    pub fn with_output(tokens: &'a [Token], output: impl io::Write + 'a) -> Self {
+        Self {
+            tokens,
+            labels: read_labels(tokens),
+            registers: [0; 256],
+            comparison: Ordering::Equal,
+            pc: 0,
+            cycles: 0,
+            call_stack: Vec::new(),
+            stack_depth: DEFAULT_STACK_DEPTH,
+            stack: vec![0; DEFAULT_STACK_SIZE],
+            memory: vec![0; DEFAULT_MEMORY_SIZE],
+            data: &[],
+            output_position: 0,
+            output: Box::new(output),
+            tracer: None,
+        }
+    }
+
+    /// Traces every executed instruction with the tracer
+    pub fn set_tracer(&mut self, tracer: Tracer<'a>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Returns the executed tokens
+    #[must_use]
+    pub const fn tokens(&self) -> &'a [Token] {
+        self.tokens
+    }
+
+    /// Returns the registers
+    #[must_use]
+    pub const fn registers(&self) -> &[i64; 256] {
+        &self.registers
+    }
+
+    /// Sets the value of a register
+    pub const fn set_register(&mut self, register: u8, value: i64) {
+        self.registers[register as usize] = value;
+    }
+
+    /// Returns the result of the last comparison or calculation compared to 0
+    #[must_use]
+    pub const fn comparison(&self) -> Ordering {
+        self.comparison
+    }
+
+    /// Returns the index of the next token to execute
+    #[must_use]
+    pub const fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Returns the number of executed cycles
+    #[must_use]
+    pub const fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    /// Returns the addresses execution returns to, the last call at the end
+    #[must_use]
+    pub fn call_stack(&self) -> &[usize] {
+        &self.call_stack
+    }
+
+    /// Returns the maximum number of nested calls
+    #[must_use]
+    pub const fn stack_depth(&self) -> usize {
+        self.stack_depth
+    }
+
+    /// Sets the maximum number of nested calls
+    pub const fn set_stack_depth(&mut self, depth: usize) {
+        self.stack_depth = depth;
+    }
+
+    /// Returns the values on the data stack, the top of the stack at the end.
+    /// Only the values up to the stack pointer are on the stack.
+    #[must_use]
+    pub fn stack(&self) -> &[i64] {
+        let pointer = usize::try_from(self.registers[STACK_POINTER as usize]).unwrap_or_default();
+        &self.stack[..pointer.min(self.stack.len())]
+    }
+
+    /// Returns the maximum number of values on the data stack
+    #[must_use]
+    pub const fn stack_size(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Sets the maximum number of values on the data stack
+    pub fn set_stack_size(&mut self, size: usize) {
+        self.stack.resize(size, 0);
+    }
+
+    /// Returns the memory
+    #[must_use]
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Returns the number of bytes of memory
+    #[must_use]
+    pub const fn memory_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Sets the number of bytes of memory, which is at least the size of the loaded data
+    /// and at most [`MAX_MEMORY_SIZE`]
+    pub fn set_memory_size(&mut self, size: usize) {
+        self.memory
+            .resize(size.min(MAX_MEMORY_SIZE).max(self.data.len()), 0);
+    }
+
+    /// Returns the data loaded into memory before running
+    #[must_use]
+    pub const fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Copies the data to the start of memory, growing the memory if the data doesn't fit
+    pub fn load_data(&mut self, data: &'a [u8]) {
+        self.data = data;
+        if self.memory.len() < data.len() {
+            self.memory.resize(data.len(), 0);
+        }
+        self.memory[..data.len()].copy_from_slice(data);
+    }
+
+    /// Returns the word at the address, or `None` if it is outside the memory
+    #[must_use]
+    pub fn read_word(&self, address: i64) -> Option<i64> {
+        let mut word = [0; WORD_SIZE];
+        word.copy_from_slice(&self.memory[self.word(address)?]);
+        Some(i64::from_le_bytes(word))
+    }
+
+    /// Returns the range of bytes of the word at the address, if it is inside the memory
+    fn word(&self, address: i64) -> Option<Range<usize>> {
+        let start = usize::try_from(address).ok()?;
+        Some(start..start.checked_add(WORD_SIZE)?).filter(|range| range.end <= self.memory.len())
+    }
+
+    /// Returns the number of bytes written as output
+    #[must_use]
+    pub const fn output_position(&self) -> usize {
+        self.output_position
+    }
+
+    /// Returns whether the program reached its end
+    #[must_use]
+    pub const fn is_halted(&self) -> bool {
+        self.pc >= self.tokens.len()
+    }
+
+    /// Returns the address to continue at after jumping
+    fn jump_target(&self, label: &Label) -> Result<usize, RuntimeError> {
+        label_to_address(label, &self.labels, self.tokens, self.pc)
+    }
+
+    /// Returns the stack pointer, erroring if it is outside the data stack
+    fn stack_pointer(&self) -> Result<usize, RuntimeError> {
+        let pointer = self.registers[STACK_POINTER as usize];
+        usize::try_from(pointer)
+            .ok()
+            .filter(|pointer| *pointer <= self.stack.len())
+            .ok_or_else(|| {
+                RuntimeError::new(RuntimeErrorKind::InvalidStackPointer(pointer), self.pc)
+            })
+    }
+
+    /// Pushes the value onto the data stack
+    fn push(&mut self, value: i64) -> Result<(), RuntimeError> {
+        let pointer = self.stack_pointer()?;
+        if pointer == self.stack.len() {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::DataStackOverflow(self.stack.len()),
+                self.pc,
+            ));
         }
+        self.stack[pointer] = value;
+        self.registers[STACK_POINTER as usize] += 1;
+        Ok(())
+    }
 
-        // Decrement the number of cycles
-        cycles = cycles.map(|cycles| cycles - 1);
+    /// Pops the top value of the data stack into the register
+    fn pop(&mut self, register: u8) -> Result<(), RuntimeError> {
+        let pointer = self
+            .stack_pointer()?
+            .checked_sub(1)
+            .ok_or_else(|| RuntimeError::new(RuntimeErrorKind::DataStackUnderflow, self.pc))?;
+        self.registers[STACK_POINTER as usize] -= 1;
+        self.registers[register as usize] = self.stack[pointer];
+        Ok(())
+    }
+
+    /// Reads the value at the offset below the top of the data stack into the register.
+    /// Offset 0 is the top of the stack.
+    fn peek(&mut self, register: u8, offset: i64) -> Result<(), RuntimeError> {
+        let pointer = self.stack_pointer()?;
+        let index = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| pointer.checked_sub(offset)?.checked_sub(1))
+            .ok_or_else(|| {
+                RuntimeError::new(RuntimeErrorKind::InvalidStackOffset(offset), self.pc)
+            })?;
+        self.registers[register as usize] = self.stack[index];
+        Ok(())
+    }
+
+    /// Loads the word at the address into the register
+    fn load(&mut self, register: u8, address: i64) -> Result<(), RuntimeError> {
+        self.registers[register as usize] = self.read_word(address).ok_or_else(|| {
+            RuntimeError::new(RuntimeErrorKind::MemoryOutOfBounds(address), self.pc)
+        })?;
+        Ok(())
+    }
+
+    /// Stores the value as word at the address
+    fn store_word(&mut self, address: i64, value: i64) -> Result<(), RuntimeError> {
+        let range = self.word(address).ok_or_else(|| {
+            RuntimeError::new(RuntimeErrorKind::MemoryOutOfBounds(address), self.pc)
+        })?;
+        self.memory[range].copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    /// Stores the result of a calculation in the register and compares it to 0
+    fn store(&mut self, register: u8, value: i64) {
+        self.registers[register as usize] = value;
+        self.comparison = value.cmp(&0);
+    }
+
+    /// Executes a single token
+    ///
+    /// # Errors
+    /// Returns an error if the instruction failed.
+    /// The program counter isn't moved in that case.
+    /// Also returns an error if the trace couldn't be written, after executing the instruction.
+    pub fn step(&mut self) -> Result<Status, RuntimeError> {
+        // Stop, if the end of the program was reached
+        let Some(token) = self.tokens.get(self.pc) else {
+            return Ok(Status::Halted);
+        };
 
         // Execute the current token
         // Store the register the result is assigned to, if applicable
+        let mut next = self.pc + 1;
+        let registers = &self.registers;
         match token {
-            Token::Out(output) => out(output, &registers),
-            Token::Mov(id, value) => registers[*id as usize] = value.take(&registers),
-            Token::Add(id, value, value2) => {
-                registers[*id as usize] =
-                    value.perform_operation(value2, &registers, i64::wrapping_add);
-                comparison = registers[*id as usize].cmp(&0);
+            Token::Out(output) => {
+                let mut writer = CountingWriter {
+                    writer: &mut self.output,
+                    written: &mut self.output_position,
+                };
+                out(output, registers, &mut writer).map_err(|error| {
+                    RuntimeError::new(RuntimeErrorKind::OutputFailed(error.kind()), self.pc)
+                })?;
             }
-            Token::Sub(id, value, value2) => {
-                registers[*id as usize] =
-                    value.perform_operation(value2, &registers, i64::wrapping_sub);
-                comparison = registers[*id as usize].cmp(&0);
+            Token::Mov(id, value) => self.registers[*id as usize] = value.take(registers),
+            Token::Jmp(label) => next = self.jump_target(label)?,
+            Token::Jl(label) if self.comparison == Ordering::Less => {
+                next = self.jump_target(label)?;
             }
-            Token::Mul(id, value, value2) => {
-                registers[*id as usize] =
-                    value.perform_operation(value2, &registers, i64::wrapping_mul);
-                comparison = registers[*id as usize].cmp(&0);
+            Token::Jg(label) if self.comparison == Ordering::Greater => {
+                next = self.jump_target(label)?;
             }
-            Token::Div(id, value, value2) => {
-                registers[*id as usize] =
-                    value.perform_operation(value2, &registers, i64::wrapping_div);
-                comparison = registers[*id as usize].cmp(&0);
+            Token::Je(label) if self.comparison == Ordering::Equal => {
+                next = self.jump_target(label)?;
             }
-            Token::Mod(id, value, value2) => {
-                registers[*id as usize] =
-                    value.perform_operation(value2, &registers, i64::wrapping_rem);
-                comparison = registers[*id as usize].cmp(&0);
+            Token::Jl(_) | Token::Jg(_) | Token::Je(_) | Token::Comment(_) | Token::Label(_) => {}
+            Token::Cmp(left, right) => self.comparison = left.compare(right, registers),
+            Token::Call(label) => {
+                if self.call_stack.len() >= self.stack_depth {
+                    return Err(RuntimeError::new(
+                        RuntimeErrorKind::StackOverflow(self.stack_depth),
+                        self.pc,
+                    ));
+                }
+                next = self.jump_target(label)?;
+                self.call_stack.push(self.pc + 1);
             }
-            Token::Jmp(label) => index = label_to_address(label, &labels),
-            Token::Jl(label) if comparison == Ordering::Less => {
-                index = label_to_address(label, &labels);
+            Token::Ret => {
+                next = self
+                    .call_stack
+                    .pop()
+                    .ok_or_else(|| RuntimeError::new(RuntimeErrorKind::StackUnderflow, self.pc))?;
             }
-            Token::Jg(label) if comparison == Ordering::Greater => {
-                index = label_to_address(label, &labels);
+            Token::Push(value) => self.push(value.take(registers))?,
+            Token::Pop(id) => self.pop(*id)?,
+            Token::Peek(id, offset) => self.peek(*id, offset.take(registers))?,
+            Token::Load(id, address) => self.load(*id, address.take(registers))?,
+            Token::Store(address, value) => {
+                self.store_word(address.take(registers), value.take(registers))?;
             }
-            Token::Je(label) if comparison == Ordering::Equal => {
-                index = label_to_address(label, &labels);
+            token => {
+                if let Some((id, result)) = calculate(token, registers, self.pc)? {
+                    self.store(id, result);
+                }
+            }
+        }
+
+        // Move to the next token and count the cycle
+        let pc = self.pc;
+        self.pc = next;
+        self.cycles += 1;
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer
+                .trace(self.cycles, pc, token, &self.registers, self.comparison)
+                .map_err(|error| {
+                    RuntimeError::new(RuntimeErrorKind::TraceFailed(error.kind()), pc)
+                })?;
+        }
+        Ok(if self.is_halted() {
+            Status::Halted
+        } else {
+            Status::Paused
+        })
+    }
+
+    /// Executes at most the passed number of cycles
+    ///
+    /// # Errors
+    /// Returns an error if an instruction failed.
+    pub fn run_for(&mut self, cycles: usize) -> Result<Status, RuntimeError> {
+        for _ in 0..cycles {
+            if self.step()? == Status::Halted {
+                return Ok(Status::Halted);
             }
-            Token::Jl(_) | Token::Jg(_) | Token::Je(_) | Token::Comment(_) | Token::Label(_) => {}
-            Token::Cmp(left, right) => comparison = left.compare(right, &registers),
         }
-        index += 1;
+        Ok(if self.is_halted() {
+            Status::Halted
+        } else {
+            Status::Paused
+        })
+    }
+
+    /// Executes the program until it halts
+    ///
+    /// # Errors
+    /// Returns an error if an instruction failed.
+    pub fn run(&mut self) -> Result<ExitState, RuntimeError> {
+        while self.step()? == Status::Paused {}
+        Ok(ExitState {
+            cycles: self.cycles,
+        })
+    }
+
+    /// Executes the program until it halts, or at most the passed number of cycles
+    ///
+    /// # Errors
+    /// Returns an error if an instruction failed or the maximum number of cycles was executed
+    /// before the program halted. The virtual machine can be resumed in the latter case.
+    pub fn execute(&mut self, cycles: Option<usize>) -> Result<ExitState, RuntimeError> {
+        let Some(limit) = cycles else {
+            return self.run();
+        };
+
+        // Error, if the program didn't halt within the maximum number of cycles
+        match self.run_for(limit)? {
+            Status::Halted => Ok(ExitState {
+                cycles: self.cycles,
+            }),
+            Status::Paused => Err(RuntimeError::new(
+                RuntimeErrorKind::CycleLimitExceeded(limit),
+                self.pc,
+            )),
+        }
+    }
+}
+
+/// Counts the bytes written to the inner writer
+struct CountingWriter<'a, W: io::Write + ?Sized> {
+    writer: &'a mut W,
+    written: &'a mut usize,
+}
+
+impl<W: io::Write + ?Sized> io::Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        *self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Executes the tokens
+///
+/// # Errors
+/// Returns an error if an instruction failed or the maximum number of cycles was executed
+/// before the program halted.
+pub fn execute(tokens: &[Token], cycles: Option<usize>) -> Result<ExitState, RuntimeError> {
+    Vm::new(tokens).execute(cycles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{error::RuntimeError, RuntimeErrorKind, Status, Vm};
+    use crate::{Program, RunOptions};
+    use std::io;
+
+    /// Runs the code after configuring the virtual machine,
+    /// returning the registers or the error that stopped the program
+    fn run(code: &str, configure: impl FnOnce(&mut Vm)) -> Result<[i64; 256], RuntimeError> {
+        let program = Program::assemble(code).unwrap();
+        let mut vm = Vm::with_output(program.tokens(), io::sink());
+        vm.load_data(program.data());
+        configure(&mut vm);
+        vm.run().map(|_| *vm.registers())
+    }
+
+    /// Returns the kind of error and the instruction that stopped the program
+    fn error(code: &str, configure: impl FnOnce(&mut Vm)) -> (RuntimeErrorKind, usize) {
+        let error = run(code, configure).unwrap_err();
+        (error.kind().clone(), error.pc())
+    }
+
+    #[test]
+    fn shifts_and_rotates() {
+        let registers = run(
+            "mov r1, -16
+            shr r2, r1, 2
+            sar r3, r1, 2
+            shr r4, r1, 66
+            sar r5, r1, -62
+            shl r6, 1, 63
+            rol r7, r1, 4
+            ror r8, 1, 1
+            shr r9, 16, 2",
+            |_| {},
+        )
+        .unwrap();
+
+        // Logical shifts fill with zeroes, arithmetic shifts keep the sign
+        assert_eq!(registers[2], 0x3FFF_FFFF_FFFF_FFFC);
+        assert_eq!(registers[3], -4);
+
+        // The amount is taken modulo 64
+        assert_eq!(registers[4], registers[2]);
+        assert_eq!(registers[5], -4);
+        assert_eq!(registers[6], i64::MIN);
+        assert_eq!(registers[7], (-16_i64).rotate_left(4));
+        assert_eq!(registers[8], i64::MIN);
+        assert_eq!(registers[9], 4);
+    }
+
+    #[test]
+    fn limits_the_call_depth() {
+        // Calls itself until the counter reaches 0
+        let code = "mov r0, 3
+            call down
+            jmp end
+            down:
+                sub r0, r0, 1
+                je back
+                call down
+            back:
+                ret
+            end:";
+        assert!(run(code, |vm| vm.set_stack_depth(3)).is_ok());
+        assert_eq!(
+            error(code, |vm| vm.set_stack_depth(2)),
+            (RuntimeErrorKind::StackOverflow(2), 6)
+        );
+        assert_eq!(error("ret", |_| {}), (RuntimeErrorKind::StackUnderflow, 0));
+    }
+
+    #[test]
+    fn pushes_pops_and_peeks() {
+        let registers = run(
+            "push 1
+            push 2
+            push 3
+            peek r1, 0
+            peek r2, 2
+            pop r3
+            sub sp, sp, 1
+            pop r4",
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(registers[1..=4], [3, 1, 3, 1]);
+        assert_eq!(registers[255], 0);
+    }
+
+    #[test]
+    fn stops_outside_the_data_stack() {
+        assert_eq!(
+            error("pop r0", |_| {}),
+            (RuntimeErrorKind::DataStackUnderflow, 0)
+        );
+        assert_eq!(
+            error("peek r0, 0", |_| {}),
+            (RuntimeErrorKind::InvalidStackOffset(0), 0)
+        );
+        assert_eq!(
+            error("push 1\npeek r0, 1", |_| {}),
+            (RuntimeErrorKind::InvalidStackOffset(1), 1)
+        );
+        assert_eq!(
+            error("push 1\npeek r0, -1", |_| {}),
+            (RuntimeErrorKind::InvalidStackOffset(-1), 1)
+        );
+        assert_eq!(
+            error("push 1\npush 2\npush 3", |vm| vm.set_stack_size(2)),
+            (RuntimeErrorKind::DataStackOverflow(2), 2)
+        );
+        assert_eq!(
+            error("mov sp, -1\npush 1", |_| {}),
+            (RuntimeErrorKind::InvalidStackPointer(-1), 1)
+        );
+    }
+
+    #[test]
+    fn loads_and_stores_words() {
+        let registers = run(
+            "mov r1, 100
+            store [r1 + 8], -2
+            store [r1 + 12], 0
+            load r2, [r1 + 8]
+            load r3, [r1 + 4]",
+            |_| {},
+        )
+        .unwrap();
+
+        // Words are little endian and can overlap
+        assert_eq!(registers[2], 0xFFFF_FFFE);
+        assert_eq!(registers[3], -0x0002_0000_0000);
+    }
+
+    #[test]
+    fn stops_outside_the_memory() {
+        let memory = |vm: &mut Vm| vm.set_memory_size(16);
+        assert!(run("mov r1, 8\nstore [r1], 1\nload r0, [r1]", memory).is_ok());
+        assert_eq!(
+            error("mov r1, 9\nstore [r1], 1", memory),
+            (RuntimeErrorKind::MemoryOutOfBounds(9), 1)
+        );
+        assert_eq!(
+            error("mov r1, 0\nload r0, [r1 - 1]", memory),
+            (RuntimeErrorKind::MemoryOutOfBounds(-1), 1)
+        );
+        assert_eq!(
+            error("mov r1, 9223372036854775807\nload r0, [r1]", memory),
+            (RuntimeErrorKind::MemoryOutOfBounds(i64::MAX), 1)
+        );
+    }
+
+    #[test]
+    fn locates_runtime_errors() {
+        let path = std::env::temp_dir().join(format!("dz_{}.basm", std::process::id()));
+        std::fs::write(&path, "mov r0, 1\n/* r1 is 0 */ div r2, r0, r1\n").unwrap();
+        let program = Program::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let error = program.run(&RunOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), &RuntimeErrorKind::DivisionByZero);
+        assert_eq!(error.pc(), 2);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Division by zero at instruction 2 ({}:2:15)",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn stops_at_the_cycle_limit() {
+        let program = Program::assemble("mov r0, 0\nloop:\nadd r0, r0, 1\njmp loop").unwrap();
+        let options = RunOptions {
+            cycles: Some(100),
+            ..RunOptions::default()
+        };
+        let error = program.run(&options).unwrap_err();
+        assert_eq!(error.kind(), &RuntimeErrorKind::CycleLimitExceeded(100));
+        assert_eq!(
+            error.to_string(),
+            "Cycle limit exceeded after 100 cycles at instruction 2 (3:1)"
+        );
+    }
+
+    #[test]
+    fn resumes_where_it_stopped() {
+        let program = Program::assemble(
+            "mov r0, 0
+            loop:
+                add r0, r0, 1
+                cmp r0, 5
+                jl loop
+            mov r1, r0",
+        )
+        .unwrap();
+        let mut vm = Vm::with_output(program.tokens(), io::sink());
+        assert_eq!(vm.run_for(3).unwrap(), Status::Paused);
+        assert_eq!((vm.pc(), vm.cycles(), vm.registers()[0]), (3, 3, 1));
+        assert_eq!(vm.run_for(1).unwrap(), Status::Paused);
+        assert_eq!((vm.pc(), vm.cycles()), (4, 4));
+
+        // Runs the rest in slices, until it halts
+        let mut slices = 0;
+        while vm.run_for(4).unwrap() == Status::Paused {
+            slices += 1;
+        }
+        assert!(vm.is_halted());
+        assert_eq!(slices, 3);
+        assert_eq!(vm.registers()[..2], [5, 5]);
+
+        // The same as running it at once
+        let mut at_once = Vm::with_output(program.tokens(), io::sink());
+        let state = at_once.run().unwrap();
+        assert_eq!(state.cycles, vm.cycles());
+        assert_eq!(at_once.registers(), vm.registers());
+        assert_eq!(vm.run_for(10).unwrap(), Status::Halted);
+        assert_eq!(vm.step().unwrap(), Status::Halted);
     }
 }
@@ -0,0 +1,153 @@
+use crate::{compile::span::Span, program::source_map::Location};
+use std::{
+    fmt::{self, Display},
+    io,
+};
+
+/// The kind of runtime error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    /// A div or mod instruction divided by 0
+    DivisionByZero,
+
+    /// A branching instruction jumped to a label or address that doesn't exist
+    InvalidJumpTarget(String),
+
+    /// The maximum number of cycles was executed before the program halted.
+    /// Contains the maximum, which is also the number of executed cycles.
+    CycleLimitExceeded(usize),
+
+    /// The output of an out instruction couldn't be written
+    OutputFailed(io::ErrorKind),
+
+    /// The trace of an executed instruction couldn't be written
+    TraceFailed(io::ErrorKind),
+
+    /// A call instruction was executed with a full call stack, contains the maximum depth
+    StackOverflow(usize),
+
+    /// A ret instruction was executed without a call to return from
+    StackUnderflow,
+
+    /// A push instruction was executed with a full data stack, contains the size of the stack
+    DataStackOverflow(usize),
+
+    /// A pop instruction was executed with an empty data stack
+    DataStackUnderflow,
+
+    /// The stack pointer was set to a value outside the data stack
+    InvalidStackPointer(i64),
+
+    /// A peek instruction read below the bottom of the data stack, contains the offset
+    InvalidStackOffset(i64),
+
+    /// A load or store instruction accessed a word outside the memory, contains the address
+    MemoryOutOfBounds(i64),
+}
+
+impl Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DivisionByZero => write!(f, "Division by zero"),
+            Self::InvalidJumpTarget(target) => write!(f, "Invalid jump target \"{target}\""),
+            Self::CycleLimitExceeded(limit) => {
+                write!(f, "Cycle limit exceeded after {limit} cycles")
+            }
+            Self::OutputFailed(kind) => write!(f, "Failed to write output: {kind}"),
+            Self::TraceFailed(kind) => write!(f, "Failed to write trace: {kind}"),
+            Self::StackOverflow(depth) => {
+                write!(f, "Call stack overflow, more than {depth} nested calls")
+            }
+            Self::StackUnderflow => write!(f, "Returned without a call to return from"),
+            Self::DataStackOverflow(size) => {
+                write!(f, "Data stack overflow, more than {size} values pushed")
+            }
+            Self::DataStackUnderflow => write!(f, "Popped from an empty data stack"),
+            Self::InvalidStackPointer(pointer) => {
+                write!(f, "Stack pointer {pointer} is outside the data stack")
+            }
+            Self::InvalidStackOffset(offset) => {
+                write!(f, "Stack offset {offset} is outside the data stack")
+            }
+            Self::MemoryOutOfBounds(address) => {
+                write!(f, "Memory address {address} is outside the memory")
+            }
+        }
+    }
+}
+
+/// An error that stopped the execution of a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError {
+    kind: RuntimeErrorKind,
+    pc: usize,
+    location: Option<Span>,
+    file: Option<String>,
+}
+
+impl RuntimeError {
+    /// Creates an error of the passed kind, caused by the instruction at the program counter
+    pub(crate) const fn new(kind: RuntimeErrorKind, pc: usize) -> Self {
+        Self {
+            kind,
+            pc,
+            location: None,
+            file: None,
+        }
+    }
+
+    /// Sets the location in the code of the instruction causing the error
+    #[must_use]
+    pub const fn with_location(mut self, location: Option<Span>) -> Self {
+        self.location = location;
+        self
+    }
+
+    /// Sets the path of the file containing the code of the instruction
+    #[must_use]
+    pub fn with_file(mut self, file: Option<String>) -> Self {
+        self.file = file;
+        self
+    }
+
+    /// Returns the kind of error
+    #[must_use]
+    pub const fn kind(&self) -> &RuntimeErrorKind {
+        &self.kind
+    }
+
+    /// Returns the index of the instruction causing the error
+    #[must_use]
+    pub const fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Returns the location in the code of the instruction causing the error, if it is known
+    #[must_use]
+    pub const fn location(&self) -> Option<Span> {
+        self.location
+    }
+
+    /// Returns the path of the file containing the code of the instruction, if it is known
+    #[must_use]
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at instruction {}", self.kind, self.pc)?;
+        if let Some(span) = self.location {
+            let location = Location {
+                file: self.file(),
+                line: span.line,
+                column: span.column,
+            };
+            write!(f, " ({location})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -0,0 +1,320 @@
+use super::Vm;
+use crate::Token;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+    fs, io,
+    path::Path,
+};
+
+/// An error that occurred while storing, loading or restoring a snapshot
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The snapshot file couldn't be read or written
+    Io(io::Error),
+
+    /// The snapshot couldn't be encoded or decoded
+    Encoding(postcard::Error),
+
+    /// The snapshot was taken while executing a different program
+    ProgramMismatch,
+
+    /// The snapshot contains a state the virtual machine can't be in
+    InvalidState,
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{error}"),
+            Self::Encoding(error) => write!(f, "Invalid snapshot: {error}"),
+            Self::ProgramMismatch => write!(f, "The snapshot was taken from a different program"),
+            Self::InvalidState => write!(f, "The snapshot contains an invalid state"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Encoding(error) => Some(error),
+            Self::ProgramMismatch | Self::InvalidState => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<postcard::Error> for SnapshotError {
+    fn from(error: postcard::Error) -> Self {
+        Self::Encoding(error)
+    }
+}
+
+/// The complete state of a virtual machine, which can be stored and restored later
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// A fingerprint of the program and its data, to only restore the state for the same program
+    program: u64,
+    registers: Vec<i64>,
+    comparison: i8,
+    pc: usize,
+    cycles: usize,
+    call_stack: Vec<usize>,
+    stack: Vec<i64>,
+    /// The memory, without the zeroes at the end
+    memory: Vec<u8>,
+    /// The number of bytes written as output
+    output_position: usize,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the virtual machine
+    #[must_use]
+    pub fn new(vm: &Vm) -> Self {
+        Self {
+            program: fingerprint(vm.tokens(), vm.data()),
+            registers: vm.registers().to_vec(),
+            comparison: vm.comparison() as i8,
+            pc: vm.pc(),
+            cycles: vm.cycles(),
+            call_stack: vm.call_stack().to_vec(),
+            stack: vm.stack().to_vec(),
+            memory: {
+                let memory = vm.memory();
+                let length = memory
+                    .iter()
+                    .rposition(|byte| *byte != 0)
+                    .map_or(0, |end| end + 1);
+                memory[..length].to_vec()
+            },
+            output_position: vm.output_position(),
+        }
+    }
+
+    /// Restores the state of the virtual machine
+    ///
+    /// # Errors
+    /// Returns an error if the virtual machine executes a different program than the one
+    /// the snapshot was taken from, or the snapshot contains an invalid state.
+    pub fn restore(&self, vm: &mut Vm) -> Result<(), SnapshotError> {
+        if self.program != fingerprint(vm.tokens(), vm.data()) {
+            return Err(SnapshotError::ProgramMismatch);
+        }
+        let registers = self
+            .registers
+            .as_slice()
+            .try_into()
+            .map_err(|_| SnapshotError::InvalidState)?;
+        let comparison = match self.comparison {
+            -1 => Ordering::Less,
+            0 => Ordering::Equal,
+            1 => Ordering::Greater,
+            _ => return Err(SnapshotError::InvalidState),
+        };
+        // Return addresses follow a call instruction, so they are never 0
+        let length = vm.tokens().len();
+        if self.pc > length
+            || self.call_stack.len() > vm.stack_depth()
+            || self
+                .call_stack
+                .iter()
+                .any(|address| *address == 0 || *address > length)
+            || self.stack.len() > vm.stack_size()
+            || self.memory.len() > vm.memory_size()
+        {
+            return Err(SnapshotError::InvalidState);
+        }
+        vm.registers = registers;
+        vm.comparison = comparison;
+        vm.pc = self.pc;
+        vm.cycles = self.cycles;
+        vm.call_stack.clone_from(&self.call_stack);
+        vm.stack[..self.stack.len()].copy_from_slice(&self.stack);
+        vm.stack[self.stack.len()..].fill(0);
+        vm.memory[..self.memory.len()].copy_from_slice(&self.memory);
+        vm.memory[self.memory.len()..].fill(0);
+        vm.output_position = self.output_position;
+        Ok(())
+    }
+
+    /// Returns the index of the next token to execute
+    #[must_use]
+    pub const fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Returns the number of executed cycles
+    #[must_use]
+    pub const fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    /// Returns the number of bytes written as output
+    #[must_use]
+    pub const fn output_position(&self) -> usize {
+        self.output_position
+    }
+
+    /// Decodes a snapshot
+    ///
+    /// # Errors
+    /// Returns an error if the bytes don't contain a valid snapshot.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        Ok(postcard::from_bytes(bytes)?)
+    }
+
+    /// Encodes the snapshot
+    ///
+    /// # Errors
+    /// Returns an error if the snapshot couldn't be encoded.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        Ok(postcard::to_stdvec(self)?)
+    }
+
+    /// Loads a snapshot from a file
+    ///
+    /// # Errors
+    /// Returns an error if the file couldn't be read or doesn't contain a valid snapshot.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Stores the snapshot in a file
+    ///
+    /// # Errors
+    /// Returns an error if the snapshot couldn't be encoded or written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+}
+
+/// Returns the 64-bit FNV-1a hash of the encoded tokens followed by the data
+fn fingerprint(tokens: &[Token], data: &[u8]) -> u64 {
+    postcard::to_stdvec(tokens)
+        .unwrap_or_default()
+        .iter()
+        .chain(data)
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Snapshot, SnapshotError};
+    use crate::{
+        execute::{Status, Vm},
+        Program,
+    };
+    use std::io;
+
+    /// Calls a function in a loop, using the stack, memory and data section
+    const CODE: &str = "
+        .data
+        total:
+            .word 5
+        .text
+        mov r0, 0
+        loop:
+            push r0
+            call add
+            pop r0
+            add r0, r0, 1
+            cmp r0, 10
+            jl loop
+        jmp end
+        add:
+            load r1, [total]
+            add r1, r1, r0
+            store [total], r1
+            ret
+        end:
+    ";
+
+    /// Returns a virtual machine executing the program, without output
+    fn vm(program: &Program) -> Vm<'_> {
+        let mut vm = Vm::with_output(program.tokens(), io::sink());
+        vm.load_data(program.data());
+        vm
+    }
+
+    #[test]
+    fn restores_the_state() {
+        let program = Program::assemble(CODE).unwrap();
+        let mut original = vm(&program);
+        assert_eq!(original.run_for(25).unwrap(), Status::Paused);
+        while original.call_stack().is_empty() {
+            original.step().unwrap();
+        }
+        let bytes = Snapshot::new(&original).to_bytes().unwrap();
+
+        let mut restored = vm(&program);
+        let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+        snapshot.restore(&mut restored).unwrap();
+        assert_eq!(snapshot.pc(), original.pc());
+        assert_eq!(restored.registers(), original.registers());
+        assert_eq!(restored.call_stack(), original.call_stack());
+        assert_eq!(restored.stack(), original.stack());
+        assert_eq!(restored.memory(), original.memory());
+        assert_eq!(restored.cycles(), original.cycles());
+
+        // Both continue the same way
+        original.run().unwrap();
+        restored.run().unwrap();
+        assert_eq!(restored.registers(), original.registers());
+        assert_eq!(restored.memory()[..8], (5_i64 + 45).to_le_bytes());
+    }
+
+    #[test]
+    fn rejects_tampered_snapshots() {
+        let program = Program::assemble(CODE).unwrap();
+        let mut original = vm(&program);
+        original.run_for(25).unwrap();
+        let snapshot = Snapshot::new(&original);
+        let invalid_state = |change: fn(&mut Snapshot)| {
+            let mut tampered = snapshot.clone();
+            change(&mut tampered);
+            matches!(
+                tampered.restore(&mut vm(&program)),
+                Err(SnapshotError::InvalidState)
+            )
+        };
+        assert!(invalid_state(|snapshot| snapshot.call_stack = vec![0]));
+        assert!(invalid_state(|snapshot| snapshot.call_stack = vec![99]));
+        assert!(invalid_state(|snapshot| snapshot.pc = 99));
+        assert!(invalid_state(|snapshot| snapshot.comparison = 2));
+        assert!(invalid_state(|snapshot| snapshot.registers.truncate(255)));
+        assert!(invalid_state(|snapshot| snapshot.memory = vec![1; 1 << 20]));
+
+        let bytes = snapshot.to_bytes().unwrap();
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes[..bytes.len() / 2]),
+            Err(SnapshotError::Encoding(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_programs() {
+        let program = Program::assemble(CODE).unwrap();
+        let snapshot = Snapshot::new(&vm(&program));
+
+        // The same instructions with other data are a different program
+        let other_data = Program::assemble(&CODE.replace(".word 5", ".word 6")).unwrap();
+        assert_eq!(other_data.tokens(), program.tokens());
+        let other_code = Program::assemble(&CODE.replace("cmp r0, 10", "cmp r0, 11")).unwrap();
+        for other in [other_data, other_code] {
+            assert!(matches!(
+                snapshot.restore(&mut vm(&other)),
+                Err(SnapshotError::ProgramMismatch)
+            ));
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::{
+    disassemble::{address_names, write_token},
+    program::source_map::SourceMap,
+    value::STACK_POINTER,
+    Program, Token,
+};
+use serde_json::json;
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display, Write as _},
+    io::{self, Write},
+    ops::Range,
+};
+
+/// How the trace is written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// A line per instruction, with aligned columns
+    #[default]
+    Human,
+
+    /// A JSON object per instruction, one per line
+    Json,
+}
+
+/// A label used in a trace range that doesn't exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndefinedLabel(pub String);
+
+impl Display for UndefinedLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Label \"{}\" doesn't exist", self.0)
+    }
+}
+
+impl std::error::Error for UndefinedLabel {}
+
+/// Returns the addresses of the instructions between two labels.
+///
+/// The range is written as `start..end`, starting at the instruction after the start label
+/// and ending before the end label. Either label can be left out to trace from the start
+/// or until the end of the program. A single label traces the instructions up to the next label.
+///
+/// # Errors
+/// Returns an error if a label doesn't exist.
+pub fn label_range(program: &Program, range: &str) -> Result<Range<usize>, UndefinedLabel> {
+    let address = |label: &str| {
+        program
+            .label(label)
+            .ok_or_else(|| UndefinedLabel(label.to_owned()))
+    };
+    let length = program.tokens().len();
+    let address_or = |label: &str, default| {
+        if label.is_empty() {
+            Ok(default)
+        } else {
+            address(label)
+        }
+    };
+    if let Some((start, end)) = range.split_once("..") {
+        return Ok(address_or(start, 0)?..address_or(end, length)?);
+    }
+
+    // Trace until the next label, removed labels included
+    let start = address(range)?;
+    let next_token = program.tokens()[start..]
+        .iter()
+        .position(|token| matches!(token, Token::Label(_)))
+        .map(|index| start + index);
+    let next_removed = program
+        .labels()
+        .iter()
+        .map(|(_, address)| *address)
+        .filter(|address| *address > start)
+        .min();
+    let end = next_token.into_iter().chain(next_removed).min();
+    Ok(start..end.unwrap_or(length))
+}
+
+/// Returns the registers the token writes: the register it stores its result in,
+/// and the stack pointer if it changes the data stack
+fn written_registers(token: &Token) -> Vec<u8> {
+    match token {
+        Token::Mov(id, _)
+        | Token::Add(id, _, _)
+        | Token::Sub(id, _, _)
+        | Token::Mul(id, _, _)
+        | Token::Div(id, _, _)
+        | Token::Mod(id, _, _)
+        | Token::And(id, _, _)
+        | Token::Or(id, _, _)
+        | Token::Xor(id, _, _)
+        | Token::Not(id, _)
+        | Token::Shl(id, _, _)
+        | Token::Shr(id, _, _)
+        | Token::Sar(id, _, _)
+        | Token::Rol(id, _, _)
+        | Token::Ror(id, _, _)
+        | Token::Peek(id, _)
+        | Token::Load(id, _) => vec![*id],
+        Token::Pop(id) if *id != STACK_POINTER => vec![*id, STACK_POINTER],
+        Token::Pop(_) | Token::Push(_) => vec![STACK_POINTER],
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the name of the comparison flag
+pub(crate) const fn flag_name(comparison: Ordering) -> &'static str {
+    match comparison {
+        Ordering::Less => "less",
+        Ordering::Equal => "equal",
+        Ordering::Greater => "greater",
+    }
+}
+
+/// Writes an entry for every instruction executed by a virtual machine.
+///
+/// Entries contain the cycle, program counter, instruction, registers written
+/// and comparison flag afterwards. Comments and labels aren't traced, as they don't do anything.
+pub struct Tracer<'a> {
+    output: Box<dyn Write + 'a>,
+    format: TraceFormat,
+    range: Option<Range<usize>>,
+    source_map: &'a SourceMap,
+    names: Vec<Option<String>>,
+}
+
+impl<'a> Tracer<'a> {
+    /// Creates a tracer for the program, writing the trace to the output.
+    /// Jumps are written with the names of the labels, and the locations of the instructions
+    /// are taken from the source map of the program.
+    #[must_use]
+    pub fn new(program: &'a Program, output: impl Write + 'a, format: TraceFormat) -> Self {
+        Self {
+            output: Box::new(output),
+            format,
+            range: None,
+            source_map: program.source_map(),
+            names: address_names(program),
+        }
+    }
+
+    /// Only traces the instructions in the range of addresses, see [`label_range`]
+    #[must_use]
+    pub const fn with_range(mut self, range: Range<usize>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Writes the entry of an executed instruction
+    ///
+    /// # Errors
+    /// Returns an error if the entry couldn't be written.
+    ///
+    /// # Panics
+    /// Never panics, as formatting into a string can't fail.
+    pub fn trace(
+        &mut self,
+        cycle: usize,
+        pc: usize,
+        token: &Token,
+        registers: &[i64; 256],
+        comparison: Ordering,
+    ) -> io::Result<()> {
+        if token.is_no_op()
+            || self
+                .range
+                .as_ref()
+                .is_some_and(|range| !range.contains(&pc))
+        {
+            return Ok(());
+        }
+        let mut instruction = String::new();
+        write_token(&mut instruction, token, &self.names).unwrap();
+        let written = written_registers(token)
+            .into_iter()
+            .map(|id| (id, registers[id as usize]));
+        let location = self.source_map.location(pc);
+        let flag = flag_name(comparison);
+
+        match self.format {
+            TraceFormat::Human => {
+                let mut effects = String::new();
+                for (id, value) in written {
+                    write!(effects, "r{id} = {value}, ").unwrap();
+                }
+                effects.push_str("flag ");
+                effects.push_str(flag);
+                let location = location.map_or_else(String::new, |location| location.to_string());
+                let line =
+                    format!("{cycle:>8} {pc:>6}  {instruction:<28}  {effects:<32}  {location}");
+                writeln!(self.output, "{}", line.trim_end())
+            }
+            TraceFormat::Json => {
+                let mut entry = json!({
+                    "cycle": cycle,
+                    "pc": pc,
+                    "instruction": instruction,
+                    "registers": {},
+                    "flag": flag,
+                });
+                for (id, value) in written {
+                    entry["registers"][format!("r{id}")] = json!(value);
+                }
+                if let Some(location) = location {
+                    entry["file"] = json!(location.file);
+                    entry["line"] = json!(location.line);
+                    entry["column"] = json!(location.column);
+                }
+                writeln!(self.output, "{entry}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceFormat, Tracer};
+    use crate::Program;
+    use serde_json::Value;
+
+    /// Runs the program, returning its trace
+    fn trace(program: &Program, format: TraceFormat) -> String {
+        let mut output = Vec::new();
+        let mut vm = program.vm();
+        vm.set_tracer(Tracer::new(program, &mut output, format));
+        vm.run().unwrap();
+        drop(vm);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn traces_the_location_of_stripped_instructions() {
+        let path = std::env::temp_dir().join(format!("trace_{}.basm", std::process::id()));
+        let code = "/* Doubles r0 */\nmov r0, 2\nloop:\n    /* twice */ add r0, r0, r0\n\
+            cmp r0, 8\njl loop\n";
+        std::fs::write(&path, code).unwrap();
+        let program = Program::load(&path).unwrap().strip();
+        std::fs::remove_file(&path).unwrap();
+        let file = path.display().to_string();
+
+        let human = trace(&program, TraceFormat::Human);
+        let lines = human.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(
+            lines[4],
+            format!(
+                "       5      1  add r0, r0, r0                \
+                r0 = 8, flag greater              {file}:4:17"
+            )
+        );
+
+        let json = trace(&program, TraceFormat::Json);
+        let entries = json
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 7);
+        assert_eq!(entries[4]["pc"], 1);
+        assert_eq!(entries[4]["file"], file);
+        assert_eq!(entries[4]["line"], 4);
+        assert_eq!(entries[4]["column"], 17);
+        assert_eq!(entries[6]["instruction"], "jl loop");
+        assert_eq!(entries[6]["line"], 6);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    compile::{self, error::Error},
+    token::directive::Directive,
+    Token,
+};
+
+/// The number of spaces instructions are indented with
+const INDENT: usize = 4;
+
+/// A line of formatted code
+struct Line {
+    /// Whether an empty line is kept before this line
+    separated: bool,
+    indent: Indent,
+    code: String,
+    comment: Option<String>,
+}
+
+/// How a line is indented
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Indent {
+    /// Labels start at the beginning of the line
+    None,
+    Instruction,
+
+    /// Comments on their own line are indented like the next line
+    Next,
+
+    /// Comments spanning multiple lines keep the indentation of the code
+    Kept(usize),
+}
+
+/// Formats the code in the canonical style
+///
+/// Labels start at the beginning of a line, every instruction is indented on its own line
+/// and arguments are separated by ", ".
+/// Comments stay where they were: comments after an instruction stay behind it,
+/// aligned with the comments on the surrounding lines, and other comments get their own line.
+/// Single empty lines between instructions are kept.
+///
+/// # Errors
+/// Returns every error found in the code, if it isn't valid byte assembly.
+pub fn format(code: &str) -> Result<String, Vec<Error>> {
+    let tokens = compile::split_spanned_tokens(code)?;
+
+    // Put every token on its own line, except for comments behind another token
+    let mut lines: Vec<Line> = Vec::new();
+    let mut end = 0;
+    for (token, span) in &tokens {
+        let newlines = code[end..span.start].matches('\n').count();
+        end = span.end;
+        let indent = match token {
+            Token::Comment(_)
+                if newlines == 0 && lines.last().is_some_and(|line| line.comment.is_none()) =>
+            {
+                if let Some(line) = lines.last_mut() {
+                    line.comment = Some(token.to_string());
+                }
+                continue;
+            }
+            Token::Comment(comment) if comment.contains('\n') => Indent::Kept(span.column - 1),
+            Token::Comment(_) => Indent::Next,
+            Token::Label(_) | Token::Directive(Directive::Data | Directive::Text) => Indent::None,
+            _ => Indent::Instruction,
+        };
+        lines.push(Line {
+            separated: newlines > 1 && !lines.is_empty(),
+            indent,
+            code: token.to_string(),
+            comment: None,
+        });
+    }
+
+    // Indent comments like the line after them
+    let mut next = Indent::Instruction;
+    for line in lines.iter_mut().rev() {
+        match line.indent {
+            Indent::Next => line.indent = next,
+            Indent::Kept(_) => {}
+            indent => next = indent,
+        }
+    }
+    for line in &mut lines {
+        let indent = match line.indent {
+            Indent::None | Indent::Next => 0,
+            Indent::Instruction => INDENT,
+            Indent::Kept(indent) => indent,
+        };
+        line.code.insert_str(0, &" ".repeat(indent));
+    }
+
+    // Write the lines, aligning comments on consecutive lines
+    let mut result = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.separated {
+            result.push('\n');
+        }
+        result.push_str(&line.code);
+        if let Some(comment) = line.comment.as_ref() {
+            let width = comment_column(&lines, index);
+            let padding = width - line.code.chars().count() + 1;
+            result.extend(std::iter::repeat_n(' ', padding));
+            result.push_str(comment);
+        }
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// Returns the width of the widest line in the group of consecutive lines with comments
+fn comment_column(lines: &[Line], index: usize) -> usize {
+    let has_comment = |line: &&Line| line.comment.is_some();
+
+    // Find the start of the group, the first line of a group may be separated
+    let mut start = index;
+    while start > 0 && !lines[start].separated && lines[start - 1].comment.is_some() {
+        start -= 1;
+    }
+    lines[start..]
+        .iter()
+        .enumerate()
+        .take_while(|(offset, line)| *offset == 0 || !line.separated)
+        .map(|(_, line)| line)
+        .take_while(has_comment)
+        .map(|line| line.code.chars().count())
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+
+    const CODE: &str = "/* Counts down */\n.text\nmov r0,3 /* start */\nmov r10,1 /* step */\n\
+        loop:   sub r0,r0,r10\n  cmp r0 ,0\n\n\n jg loop\n/*\n   done\n*/\nout \"{0}\"";
+
+    #[test]
+    fn formats_in_the_canonical_style() {
+        assert_eq!(
+            format(CODE).unwrap(),
+            "/* Counts down */\n\
+            .text\n    \
+            mov r0, 3  /* start */\n    \
+            mov r10, 1 /* step */\n\
+            loop:\n    \
+            sub r0, r0, r10\n    \
+            cmp r0, 0\n\n    \
+            jg loop\n\
+            /*\n   done\n*/\n    \
+            out \"{0}\"\n"
+        );
+    }
+
+    #[test]
+    fn keeps_formatted_code() {
+        let formatted = format(CODE).unwrap();
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        for example in ["comments", "data", "memory", "stack"] {
+            let code = std::fs::read_to_string(format!("tests/{example}.basm")).unwrap();
+            let formatted = format(&code).unwrap();
+            assert_eq!(format(&formatted).unwrap(), formatted, "{example}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_code() {
+        let errors = format("mov r0, 1\nmvo r1, 2").unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+}
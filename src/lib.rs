@@ -0,0 +1,21 @@
+#![warn(clippy::pedantic, clippy::nursery)]
+//! Byte assembly is a simple programming language compiled to bytecode.
+//!
+//! This crate contains the assembler, turning `.basm` code into tokens,
+//! and the virtual machine executing those tokens.
+//! The [`Program`] type ties both together and handles loading and storing `.basmo` bytecode.
+
+pub mod compile;
+pub mod dap;
+pub mod debugger;
+pub mod disassemble;
+pub mod execute;
+pub mod format;
+mod framing;
+pub mod lsp;
+pub mod program;
+pub mod token;
+pub mod value;
+
+pub use program::{Encoding, Program, RunOptions, SaveOptions};
+pub use token::Token;
@@ -0,0 +1,796 @@
+use crate::{
+    compile::{
+        self,
+        error::{Error, Severity},
+        DIRECTIVES, MNEMONICS,
+    },
+    framing::{read_message, write_message},
+    program::verify::VerifyError,
+    token::directive::Directive,
+    Program, Token,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+/// The semantic token types, the index is used as type in the encoded tokens
+const TOKEN_TYPES: [&str; 6] = [
+    "keyword", "function", "variable", "number", "string", "comment",
+];
+
+/// The semantic token modifier marking label declarations
+const DECLARATION_MODIFIER: u32 = 1;
+
+/// The mnemonics of the branching instructions, which are followed by a label
+const JUMPS: [&str; 5] = ["jmp", "jl", "jg", "je", "call"];
+
+/// The error code for requests the server doesn't handle
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Returns the documentation of an instruction or directive
+fn documentation(mnemonic: &str) -> Option<&'static str> {
+    Some(match mnemonic {
+        "out" => {
+            "`out \"text\"`\n\nWrites the text, replacing `{n}` by the value of register n.\n\
+            Without quotes, writes the word followed by a new line."
+        }
+        "mov" => "`mov ra, value`\n\nStores the value in register a.",
+        "add" => {
+            "`add ra, left, right`\n\nStores left + right in register a \
+            and compares the result to 0."
+        }
+        "sub" => {
+            "`sub ra, left, right`\n\nStores left - right in register a \
+            and compares the result to 0."
+        }
+        "mul" => {
+            "`mul ra, left, right`\n\nStores left * right in register a \
+            and compares the result to 0."
+        }
+        "div" => {
+            "`div ra, left, right`\n\nStores left / right in register a \
+            and compares the result to 0. Fails if right is 0."
+        }
+        "mod" => {
+            "`mod ra, left, right`\n\nStores the remainder of left / right in register a \
+            and compares the result to 0. Fails if right is 0."
+        }
+        "and" => {
+            "`and ra, left, right`\n\nStores the bitwise and of left and right in register a \
+            and compares the result to 0."
+        }
+        "or" => {
+            "`or ra, left, right`\n\nStores the bitwise or of left and right in register a \
+            and compares the result to 0."
+        }
+        "xor" => {
+            "`xor ra, left, right`\n\nStores the bitwise exclusive or of left and right \
+            in register a and compares the result to 0."
+        }
+        "not" => {
+            "`not ra, value`\n\nStores the value with every bit flipped in register a \
+            and compares the result to 0."
+        }
+        "shl" => {
+            "`shl ra, value, amount`\n\nStores the value shifted left by amount bits \
+            in register a and compares the result to 0. The amount is taken modulo 64."
+        }
+        "shr" => {
+            "`shr ra, value, amount`\n\nStores the value shifted right by amount bits, \
+            filling with zeroes, in register a and compares the result to 0. \
+            The amount is taken modulo 64."
+        }
+        "sar" => {
+            "`sar ra, value, amount`\n\nStores the value shifted right by amount bits, \
+            keeping the sign, in register a and compares the result to 0. \
+            The amount is taken modulo 64."
+        }
+        "rol" => {
+            "`rol ra, value, amount`\n\nStores the value rotated left by amount bits \
+            in register a and compares the result to 0."
+        }
+        "ror" => {
+            "`ror ra, value, amount`\n\nStores the value rotated right by amount bits \
+            in register a and compares the result to 0."
+        }
+        "cmp" => "`cmp left, right`\n\nCompares left to right, for the conditional jumps.",
+        "jmp" => "`jmp label`\n\nContinues execution after the label.",
+        "jl" => "`jl label`\n\nJumps to the label, if the last comparison was lower.",
+        "jg" => "`jg label`\n\nJumps to the label, if the last comparison was greater.",
+        "je" => "`je label`\n\nJumps to the label, if the last comparison was equal.",
+        "call" => {
+            "`call label`\n\nJumps to the label, remembering to return after the call. \
+            Fails if the call stack is full."
+        }
+        "ret" => {
+            "`ret`\n\nReturns to the instruction after the last call. \
+            Fails if there is no call to return from."
+        }
+        "push" => {
+            "`push value`\n\nPushes the value onto the data stack and increments `sp`. \
+            Fails if the data stack is full."
+        }
+        "pop" => {
+            "`pop ra`\n\nStores the top value of the data stack in register a and decrements `sp`. \
+            Fails if the data stack is empty."
+        }
+        "peek" => {
+            "`peek ra, offset`\n\nStores the value at the offset below the top of the data stack \
+            in register a, offset 0 being the top. Fails if the offset is outside the stack."
+        }
+        "load" => {
+            "`load ra, [address]`\n\nLoads the 8-byte word at the address in memory into register a. \
+            Addresses are written as `[rb]`, `[number]` or `[rb + number]`."
+        }
+        "store" => {
+            "`store [address], value`\n\nStores the value as 8-byte word at the address in memory. \
+            Addresses are written as `[rb]`, `[number]` or `[rb + number]`."
+        }
+        _ => return directive_documentation(mnemonic),
+    })
+}
+
+/// Returns the documentation of a directive
+fn directive_documentation(directive: &str) -> Option<&'static str> {
+    Some(match directive {
+        ".data" => {
+            "`.data`\n\nStarts the data section, which is loaded into memory at address 0. \
+            Labels in it can be used in memory addresses, like `[label + 8]`."
+        }
+        ".text" => "`.text`\n\nEnds the data section, the following lines are instructions.",
+        ".word" => "`.word 1, 2, 3`\n\nAdds 8-byte little endian words to the data section.",
+        ".byte" => "`.byte 1, 2, 3`\n\nAdds bytes to the data section.",
+        ".ascii" => "`.ascii \"text\"`\n\nAdds the UTF-8 bytes of the text to the data section.",
+        ".zero" => "`.zero count`\n\nAdds the number of zero bytes to the data section.",
+        _ => return None,
+    })
+}
+
+/// What a word in the code is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WordKind {
+    Comment,
+    String,
+    Mnemonic,
+    Label,
+    Jump,
+    Register,
+    Number,
+    Other,
+}
+
+/// A word in the code, with its byte offsets
+#[derive(Debug, Clone, Copy)]
+struct Word<'a> {
+    text: &'a str,
+    kind: WordKind,
+    start: usize,
+    end: usize,
+}
+
+impl<'a> Word<'a> {
+    /// Returns the name of the label declared or jumped to, if the word is a label
+    fn label(&self) -> Option<&'a str> {
+        match self.kind {
+            WordKind::Label => self.text.strip_suffix(':'),
+            WordKind::Jump => Some(self.text),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the parts of the text between whitespace, separators and the brackets around
+/// addresses, with their byte offsets
+fn parts(text: &str) -> Vec<(usize, &str)> {
+    let mut parts = Vec::new();
+    let mut start = None;
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        let separator = c.is_whitespace() || matches!(c, ',' | '[' | ']');
+        match start {
+            None if !separator => start = Some(index),
+            Some(first) if separator => {
+                parts.push((first, &text[first..index]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    parts
+}
+
+/// Splits the code into words, classified by the tokens the assembler reads from the code.
+/// Lines the assembler can't read have no words.
+fn words(code: &str) -> Vec<Word<'_>> {
+    let (tokens, _) = compile::read_spanned_tokens(code);
+    let mut words = Vec::new();
+    for (token, span) in &tokens {
+        let word = |start: usize, end: usize, kind| Word {
+            text: &code[start..end],
+            kind,
+            start,
+            end,
+        };
+        let kind = match token {
+            Token::Comment(_) => Some(WordKind::Comment),
+            Token::Label(_) => Some(WordKind::Label),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            words.push(word(span.start, span.end, kind));
+            continue;
+        }
+
+        // The instruction or directive is followed by its arguments
+        let parts = parts(&code[span.start..span.end]);
+        let Some((_, mnemonic)) = parts.first() else {
+            continue;
+        };
+        words.push(word(
+            span.start,
+            span.start + mnemonic.len(),
+            WordKind::Mnemonic,
+        ));
+        let Some((offset, _)) = parts.get(1) else {
+            continue;
+        };
+        if let Token::Out(_) | Token::Directive(Directive::Ascii(_)) = token {
+            words.push(word(span.start + offset, span.end, WordKind::String));
+            continue;
+        }
+        for (offset, text) in &parts[1..] {
+            let kind = match token {
+                Token::Jmp(_) | Token::Jl(_) | Token::Jg(_) | Token::Je(_) | Token::Call(_) => {
+                    WordKind::Jump
+                }
+                _ if *text == "sp"
+                    || text
+                        .strip_prefix('r')
+                        .is_some_and(|id| id.parse::<u8>().is_ok()) =>
+                {
+                    WordKind::Register
+                }
+                _ if text.parse::<i64>().is_ok() => WordKind::Number,
+
+                // Labels in memory addresses refer to the data section
+                Token::Load(_, address) | Token::Store(address, _)
+                    if address.label.as_deref() == Some(*text) =>
+                {
+                    WordKind::Jump
+                }
+                _ => WordKind::Other,
+            };
+            let start = span.start + offset;
+            words.push(word(start, start + text.len(), kind));
+        }
+    }
+    words
+}
+
+/// An open document, with the byte offsets at which its lines start
+struct Document {
+    text: String,
+    lines: Vec<usize>,
+}
+
+impl Document {
+    /// Creates a document, indexing the starts of its lines
+    fn new(text: String) -> Self {
+        let lines = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        Self { text, lines }
+    }
+
+    /// Converts a byte offset to an LSP position, counting UTF-16 code units
+    fn position(&self, offset: usize) -> (u32, u32) {
+        let offset = offset.min(self.text.len());
+        let line = self.lines.partition_point(|start| *start <= offset) - 1;
+        let character = self.text[self.lines[line]..offset].encode_utf16().count();
+        (
+            u32::try_from(line).unwrap_or(u32::MAX),
+            u32::try_from(character).unwrap_or(u32::MAX),
+        )
+    }
+
+    /// Converts an LSP position to a byte offset, clamped to the line
+    fn offset(&self, position: &Value) -> usize {
+        let line = position["line"].as_u64().unwrap_or_default();
+        let character = position["character"].as_u64().unwrap_or_default();
+        let Some(start) = usize::try_from(line)
+            .ok()
+            .and_then(|line| self.lines.get(line))
+        else {
+            return self.text.len();
+        };
+
+        // Move over the characters, until the position is reached
+        let mut units = 0;
+        for (index, c) in self.text[*start..].char_indices() {
+            if units >= character || c == '\n' {
+                return start + index;
+            }
+            units += c.len_utf16() as u64;
+        }
+        self.text.len()
+    }
+
+    /// Returns the range between the byte offsets
+    fn range(&self, start: usize, end: usize) -> Value {
+        let position = |offset| {
+            let (line, character) = self.position(offset);
+            json!({ "line": line, "character": character })
+        };
+        json!({ "start": position(start), "end": position(end) })
+    }
+
+    /// Converts an assembler error to an LSP diagnostic
+    fn diagnostic(&self, error: &Error) -> Value {
+        let mut message = error.kind().to_string();
+        if let Some(help) = error.kind().help() {
+            message.push_str("\nhelp: ");
+            message.push_str(&help);
+        }
+        if let Some(suggestion) = error.suggestion() {
+            message.push_str("\nhelp: did you mean `");
+            message.push_str(suggestion);
+            message.push_str("`?");
+        }
+        let span = error.span();
+        json!({
+            "range": self.range(span.start, span.end),
+            "severity": match error.kind().severity() {
+                Severity::Error => 1,
+            },
+            "code": error.kind().code(),
+            "source": "byte_assembly",
+            "message": message,
+        })
+    }
+
+    /// Converts a problem found by the verifier to an LSP diagnostic
+    fn verify_diagnostic(&self, error: &VerifyError) -> Value {
+        let (start, end) = error
+            .location()
+            .map_or((0, 0), |span| (span.start, span.end));
+        json!({
+            "range": self.range(start, end),
+            "severity": 1,
+            "source": "byte_assembly",
+            "message": error.kind().to_string(),
+        })
+    }
+
+    /// Encodes the words as semantic tokens, relative to the previous token
+    fn semantic_tokens(&self, words: &[Word]) -> Vec<u32> {
+        let mut data = Vec::new();
+        let (mut last_line, mut last_start) = (0, 0);
+        for word in words {
+            let (kind, modifiers) = match word.kind {
+                WordKind::Mnemonic => (0, 0),
+                WordKind::Label => (1, DECLARATION_MODIFIER),
+                WordKind::Jump => (1, 0),
+                WordKind::Register => (2, 0),
+                WordKind::Number => (3, 0),
+                WordKind::String => (4, 0),
+                WordKind::Comment => (5, 0),
+                WordKind::Other => continue,
+            };
+
+            // Split words over multiple lines, as tokens can't contain new lines
+            let mut start = word.start;
+            for part in word.text.split('\n') {
+                let length = part.encode_utf16().count();
+                let (line, character) = self.position(start);
+                start += part.len() + 1;
+                if length == 0 {
+                    continue;
+                }
+                let delta_start = if line == last_line {
+                    character - last_start
+                } else {
+                    character
+                };
+                data.extend([
+                    line - last_line,
+                    delta_start,
+                    u32::try_from(length).unwrap_or(u32::MAX),
+                    kind,
+                    modifiers,
+                ]);
+                (last_line, last_start) = (line, character);
+            }
+        }
+        data
+    }
+}
+
+/// A language server, keeping track of the open documents
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, Document>,
+}
+
+impl<W: Write> Server<W> {
+    /// Sends a notification to the client
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        let mut notification = json!({ "jsonrpc": "2.0", "method": method });
+        notification["params"] = params;
+        write_message(&mut self.output, &notification)
+    }
+
+    /// Sends the diagnostics of the assembler for the document,
+    /// or those of the verifier if the document assembles
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = self
+            .documents
+            .get(uri)
+            .map(|document| match Program::assemble(&document.text) {
+                Ok(program) => program
+                    .verify()
+                    .err()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|error| document.verify_diagnostic(error))
+                    .collect(),
+                Err(errors) => errors
+                    .iter()
+                    .map(|error| document.diagnostic(error))
+                    .collect::<Vec<_>>(),
+            })
+            .unwrap_or_default();
+        self.notify(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+
+    /// Handles a notification, returns whether the server should exit
+    fn notification(&mut self, method: &str, params: &Value) -> io::Result<bool> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents
+                    .insert(uri.to_owned(), Document::new(text.to_owned()));
+                self.publish_diagnostics(uri)?;
+            }
+            "textDocument/didChange" => {
+                // Only full synchronization is supported, so the last change is the whole text
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let Some(text) = text {
+                    self.documents
+                        .insert(uri.to_owned(), Document::new(text.to_owned()));
+                    self.publish_diagnostics(uri)?;
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish_diagnostics(uri)?;
+            }
+            "exit" => return Ok(true),
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// Handles a request, returns the result or `None` if the method isn't supported
+    fn request(&self, method: &str, params: &Value) -> Option<Value> {
+        if method == "initialize" {
+            return Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                    "semanticTokensProvider": {
+                        "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": ["declaration"] },
+                        "full": true,
+                    },
+                },
+                "serverInfo": { "name": "byte_assembly" },
+            }));
+        }
+        if method == "shutdown" {
+            return Some(Value::Null);
+        }
+
+        // Every other request works on a document
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let empty = Document::new(String::new());
+        let document = self.documents.get(uri).unwrap_or(&empty);
+        let words = words(&document.text);
+        let offset = document.offset(&params["position"]);
+        let word = words
+            .iter()
+            .find(|word| word.start <= offset && offset <= word.end);
+        let location =
+            |word: &Word| json!({ "uri": uri, "range": document.range(word.start, word.end) });
+        Some(match method {
+            "textDocument/definition" => word
+                .and_then(Word::label)
+                .and_then(|label| {
+                    words
+                        .iter()
+                        .find(|word| word.kind == WordKind::Label && word.label() == Some(label))
+                })
+                .map_or(Value::Null, location),
+            "textDocument/references" => {
+                let declaration = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or_default();
+                let references = word.and_then(Word::label).map_or_else(Vec::new, |label| {
+                    words
+                        .iter()
+                        .filter(|word| word.label() == Some(label))
+                        .filter(|word| declaration || word.kind == WordKind::Jump)
+                        .map(location)
+                        .collect()
+                });
+                json!(references)
+            }
+            "textDocument/hover" => word
+                .and_then(|word| {
+                    let contents = match word.kind {
+                        WordKind::Mnemonic => documentation(word.text)?.to_owned(),
+                        WordKind::Register if word.text == "sp" => {
+                            "Register 255, the stack pointer".to_owned()
+                        }
+                        WordKind::Register => format!("Register {}", &word.text[1..]),
+                        _ => return None,
+                    };
+                    Some(json!({
+                        "contents": { "kind": "markdown", "value": contents },
+                        "range": document.range(word.start, word.end),
+                    }))
+                })
+                .unwrap_or(Value::Null),
+            "textDocument/completion" => json!(completions(&document.text, &words, offset)),
+            "textDocument/semanticTokens/full" => {
+                json!({ "data": document.semantic_tokens(&words) })
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// Returns the completions at the offset, depending on the instruction before it
+fn completions(code: &str, words: &[Word], offset: usize) -> Vec<Value> {
+    // Find the first word on the line, if it is followed by the word being typed
+    let line_start = code[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let line = &code[line_start..offset];
+    let first = line
+        .split_whitespace()
+        .next()
+        .filter(|first| line.trim_start().len() > first.len());
+    let item = |label: &str, kind: u32| json!({ "label": label, "kind": kind });
+    match first {
+        // Suggest instructions at the start of a line
+        None => MNEMONICS
+            .iter()
+            .chain(&DIRECTIVES)
+            .map(|mnemonic| {
+                let mut item = item(mnemonic, 14);
+                item["documentation"] =
+                    json!({ "kind": "markdown", "value": documentation(mnemonic) });
+                item
+            })
+            .collect(),
+
+        // Suggest labels after a branching instruction
+        Some(first) if JUMPS.contains(&first) => words
+            .iter()
+            .filter(|word| word.kind == WordKind::Label)
+            .filter_map(Word::label)
+            .map(|label| item(label, 3))
+            .collect(),
+
+        // Suggest registers as arguments
+        Some(first) if MNEMONICS.contains(&first) && first != "out" => (0..=u8::MAX)
+            .map(|id| item(&format!("r{id}"), 6))
+            .chain(std::iter::once(item("sp", 6)))
+            .collect(),
+        Some(_) => Vec::new(),
+    }
+}
+
+/// Runs a Language Server Protocol server on the input and output
+///
+/// Requests and notifications are read from the input, responses and notifications are written
+/// to the output. Stops after the exit notification or at the end of the input.
+///
+/// # Errors
+/// Returns an error if the input couldn't be read or the output couldn't be written.
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+    };
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        // Messages without id are notifications, which don't get a response
+        let Some(id) = message.get("id") else {
+            if server.notification(method, params)? {
+                break;
+            }
+            continue;
+        };
+        let response = server.request(method, params).map_or_else(
+            || {
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("Unsupported method \"{method}\""),
+                    },
+                })
+            },
+            |result| json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        );
+        write_message(&mut server.output, &response)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+    use crate::framing::{read_message, write_message};
+    use serde_json::{json, Value};
+    use std::io::Cursor;
+
+    const URI: &str = "file:///test.basm";
+
+    /// Opens a document with the code, sends the requests and returns the messages of the server
+    fn session(code: &str, requests: &[(&str, Value)]) -> Vec<Value> {
+        let mut input = Vec::new();
+        let open = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "text": code } },
+        });
+        write_message(&mut input, &open).unwrap();
+        for (id, (method, params)) in requests.iter().enumerate() {
+            let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+            write_message(&mut input, &request).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(Cursor::new(input), &mut output).unwrap();
+        let mut output = output.as_slice();
+        std::iter::from_fn(|| read_message(&mut output).unwrap()).collect()
+    }
+
+    /// Sends a single request at the position, returns its result
+    fn request(code: &str, method: &str, line: u32, character: u32) -> Value {
+        let params = json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+            "context": { "includeDeclaration": true },
+        });
+        session(code, &[(method, params)])
+            .into_iter()
+            .find(|message| message["id"] == 0)
+            .unwrap()["result"]
+            .take()
+    }
+
+    /// Returns the start of the range as line and character
+    fn start(range: &Value) -> (u64, u64) {
+        let start = &range["start"];
+        (
+            start["line"].as_u64().unwrap(),
+            start["character"].as_u64().unwrap(),
+        )
+    }
+
+    const CODE: &str = "/* Counts to 3 */\nloop:\n    add r0, r0, 1\n    cmp r0, 3\n    jl loop\n";
+
+    #[test]
+    fn publishes_diagnostics() {
+        let messages = session("mov r0, 1\nmvo r1, 2\n", &[]);
+        let diagnostics = &messages[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["code"], "E0003");
+        assert_eq!(start(&diagnostics[0]["range"]), (1, 0));
+        assert!(diagnostics[0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("did you mean `mov`?"));
+
+        let messages = session(CODE, &[]);
+        assert_eq!(messages[0]["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn publishes_verifier_diagnostics() {
+        let code = ".data\nvalue:\n    .word 1\n.text\n    load r0, [value]\n    load r1, [64]\n";
+        let messages = session(code, &[]);
+        let diagnostics = messages[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(start(&diagnostics[0]["range"]), (5, 4));
+        assert_eq!(
+            diagnostics[0]["message"],
+            "Memory address 64 is outside the data section"
+        );
+    }
+
+    #[test]
+    fn finds_definitions_and_references() {
+        let definition = request(CODE, "textDocument/definition", 4, 8);
+        assert_eq!(start(&definition["range"]), (1, 0));
+
+        let references = request(CODE, "textDocument/references", 1, 2);
+        let references = references.as_array().unwrap();
+        assert_eq!(references.len(), 2);
+        assert_eq!(start(&references[1]["range"]), (4, 7));
+    }
+
+    #[test]
+    fn finds_labels_in_the_data_section() {
+        let code = ".data\ntable:\n    .word 1, 2\n.text\nload r0, [table + 8]\n";
+        let definition = request(code, "textDocument/definition", 4, 12);
+        assert_eq!(start(&definition["range"]), (1, 0));
+    }
+
+    #[test]
+    fn shows_documentation() {
+        let hover = request(CODE, "textDocument/hover", 2, 5);
+        assert!(hover["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .starts_with("`add ra, left, right`"));
+        let hover = request(CODE, "textDocument/hover", 2, 9);
+        assert_eq!(hover["contents"]["value"], "Register 0");
+        assert_eq!(request(CODE, "textDocument/hover", 0, 3), Value::Null);
+    }
+
+    #[test]
+    fn completes_instructions_labels_and_registers() {
+        let code = "start:\n    jmp \n    add \n    ";
+        let labels = request(code, "textDocument/completion", 1, 8);
+        assert_eq!(labels, json!([{ "label": "start", "kind": 3 }]));
+        let registers = request(code, "textDocument/completion", 2, 8);
+        assert_eq!(registers.as_array().unwrap().len(), 257);
+        let instructions = request(code, "textDocument/completion", 3, 4);
+        assert!(instructions
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|item| item["label"] == ".word"));
+    }
+
+    #[test]
+    fn encodes_semantic_tokens() {
+        let code = "loop:\n    add r0, r0, 1\n    jmp loop /* ä */ out \"x\"\n";
+        let tokens = request(code, "textDocument/semanticTokens/full", 0, 0);
+        assert_eq!(
+            tokens["data"],
+            json!([
+                0, 0, 5, 1, 1, // loop:
+                1, 4, 3, 0, 0, // add
+                0, 4, 2, 2, 0, // r0
+                0, 4, 2, 2, 0, // r0
+                0, 4, 1, 3, 0, // 1
+                1, 4, 3, 0, 0, // jmp
+                0, 4, 4, 1, 0, // loop
+                0, 5, 7, 5, 0, // /* ä */
+                0, 8, 3, 0, 0, // out
+                0, 4, 3, 4, 0, // "x"
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_methods() {
+        let messages = session(CODE, &[("textDocument/rename", json!({}))]);
+        assert_eq!(messages[1]["error"]["code"], super::METHOD_NOT_FOUND);
+    }
+}
@@ -1,19 +1,133 @@
 #![warn(clippy::pedantic, clippy::nursery)]
 
-use clap::Parser;
-use std::{fs::File, io::BufWriter, path::PathBuf, time::Instant};
+use byte_assembly::{
+    compile::diagnostic::{Diagnostic, Renderer},
+    dap,
+    debugger::Debugger,
+    disassemble::disassemble,
+    execute::{
+        error::RuntimeErrorKind,
+        snapshot::Snapshot,
+        trace::{label_range, TraceFormat, Tracer},
+        DEFAULT_MEMORY_SIZE, DEFAULT_STACK_DEPTH, DEFAULT_STACK_SIZE,
+    },
+    format::format,
+    lsp,
+    program::Error,
+    Encoding, Program, SaveOptions, Token,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
-mod compile;
-mod execute;
-mod token;
-mod value;
+/// When to color the output
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Color {
+    /// Only color the output if it is written to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+/// How diagnostics are printed
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum MessageFormat {
+    /// Human-readable diagnostics, showing the code causing them
+    Human,
+
+    /// A JSON object per diagnostic, one per line on stdout
+    Json,
+}
+
+/// How the bytecode is encoded
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum BytecodeEncoding {
+    /// The tokens serialized with postcard
+    Postcard,
+
+    /// Fixed-width instructions with pools for numbers and strings
+    Compact,
+}
+
+impl From<BytecodeEncoding> for Encoding {
+    fn from(encoding: BytecodeEncoding) -> Self {
+        match encoding {
+            BytecodeEncoding::Postcard => Self::Postcard,
+            BytecodeEncoding::Compact => Self::Compact,
+        }
+    }
+}
+
+/// How the trace of executed instructions is written
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum TraceOutputFormat {
+    /// A line per instruction, with aligned columns
+    Human,
+
+    /// A JSON object per instruction, one per line
+    Json,
+}
+
+impl From<TraceOutputFormat> for TraceFormat {
+    fn from(format: TraceOutputFormat) -> Self {
+        match format {
+            TraceOutputFormat::Human => Self::Human,
+            TraceOutputFormat::Json => Self::Json,
+        }
+    }
+}
+
+/// Tools working on programs, instead of compiling and running them
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Debug a program interactively
+    Debug {
+        /// The .basm or .basmo file to debug
+        file: PathBuf,
+    },
+
+    /// Run a Debug Adapter Protocol server over stdin and stdout
+    Dap,
+
+    /// Run a Language Server Protocol server for .basm files over stdin and stdout
+    Lsp,
+
+    /// Format .basm files in place
+    Fmt {
+        /// The .basm files to format
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Only check whether the files are formatted, exiting with code 1 if any isn't
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Turn a .basmo bytecode file back into .basm code
+    Disasm {
+        /// The .basmo file to disassemble
+        file: PathBuf,
 
-use token::Token;
+        /// The file to write the code to, instead of stdout
+        #[arg(short)]
+        out: Option<PathBuf>,
+    },
+}
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[allow(clippy::struct_excessive_bools)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The input file
-    file: PathBuf,
+    #[arg(required = true)]
+    file: Option<PathBuf>,
 
     /// The output file
     #[arg(short)]
@@ -34,34 +148,245 @@ struct Args {
     // The maximum number of cycles to run
     #[arg(short, long)]
     cycles: Option<usize>,
+
+    /// The maximum number of nested calls
+    #[arg(long, default_value_t = DEFAULT_STACK_DEPTH)]
+    stack_depth: usize,
+
+    /// The maximum number of values on the data stack
+    #[arg(long, default_value_t = DEFAULT_STACK_SIZE)]
+    stack_size: usize,
+
+    /// The number of bytes of memory, at most 16 MiB
+    #[arg(long, default_value_t = DEFAULT_MEMORY_SIZE)]
+    memory_size: usize,
+
+    /// The maximum number of compilation errors to print
+    #[arg(long, default_value_t = 20)]
+    max_errors: usize,
+
+    /// When to color the errors
+    #[arg(long, value_enum, default_value_t = Color::Auto)]
+    color: Color,
+
+    /// Store the state of the program in this file, if it is stopped before halting
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+
+    /// Continue running the program from the state stored in this snapshot file
+    #[arg(long)]
+    resume: Option<PathBuf>,
+
+    /// How to print diagnostics
+    #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+    message_format: MessageFormat,
+
+    /// How to encode the bytecode stored with -o
+    #[arg(long, value_enum, default_value_t = BytecodeEncoding::Postcard)]
+    encoding: BytecodeEncoding,
+
+    /// Remove comments and labels from the program, updating the addresses jumped to
+    #[arg(long)]
+    strip: bool,
+
+    /// Store the names of removed labels and the locations in the code in the bytecode
+    #[arg(long)]
+    debug_info: bool,
+
+    /// Log every executed instruction to stderr, in the passed format
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "human")]
+    trace: Option<TraceOutputFormat>,
+
+    /// Only trace the instructions between two labels, written as `start..end`.
+    /// Either label can be left out, a single label traces until the next label
+    #[arg(long, requires = "trace")]
+    trace_range: Option<String>,
+
+    /// Write the trace to this file instead of stderr
+    #[arg(long, requires = "trace")]
+    trace_file: Option<PathBuf>,
+}
+
+impl Args {
+    /// Returns the input file, which is required if no subcommand is used
+    fn file(&self) -> &Path {
+        self.file
+            .as_deref()
+            .expect("The input file is required without a subcommand")
+    }
+}
+
+/// Prints the errors found while loading the program as JSON, one diagnostic per line
+fn print_json_load_error(error: &Error, file: &Path) {
+    let file = file.display().to_string();
+    match error {
+        Error::Compile(errors) => {
+            for error in errors {
+                println!("{}", Diagnostic::from_error(error, &file).to_json());
+            }
+        }
+        Error::Verify(errors) => {
+            for error in errors {
+                let diagnostic = Diagnostic {
+                    span: error.location(),
+                    ..Diagnostic::new(error.kind().to_string(), &file)
+                };
+                println!("{}", diagnostic.to_json());
+            }
+        }
+        error => println!("{}", Diagnostic::new(error.to_string(), &file).to_json()),
+    }
+}
+
+/// Prints the errors found while loading the program
+fn print_load_error(error: &Error, file: &Path, args: &Args) {
+    if matches!(args.message_format, MessageFormat::Json) {
+        print_json_load_error(error, file);
+        return;
+    }
+    let errors = match error {
+        Error::Compile(errors) => errors,
+        Error::Verify(errors) => {
+            for error in errors {
+                eprintln!("error: {error}");
+            }
+            eprintln!(
+                "error: could not load the program due to {}",
+                count_errors(errors.len())
+            );
+            return;
+        }
+        error => {
+            eprintln!("error: {error}");
+            return;
+        }
+    };
+
+    // Print the errors up to the maximum
+    // Show the code causing the error, if the code can still be read
+    let color = match args.color {
+        Color::Auto => std::io::stderr().is_terminal(),
+        Color::Always => true,
+        Color::Never => false,
+    };
+    let source = std::fs::read_to_string(file).ok();
+    let file = file.display().to_string();
+    for error in errors.iter().take(args.max_errors) {
+        match source.as_deref() {
+            Some(source) => eprintln!(
+                "{}",
+                Renderer::new(source, &file).color(color).render(error)
+            ),
+            None => eprintln!("error: {error}"),
+        }
+    }
+
+    // Tell the user how many errors weren't printed
+    if errors.len() > args.max_errors {
+        eprintln!(
+            "... {} not shown",
+            count_errors(errors.len() - args.max_errors)
+        );
+    }
+    eprintln!(
+        "error: could not compile due to {}",
+        count_errors(errors.len())
+    );
+}
+
+/// Writes the number of errors, like "1 error" or "2 errors"
+fn count_errors(count: usize) -> String {
+    if count == 1 {
+        "1 error".to_owned()
+    } else {
+        format!("{count} errors")
+    }
 }
 
-fn load_bytecode(args: &Args) -> Vec<Token> {
-    // Take the extension
-    let Some(extension) = args.file.extension() else {
-        panic!("Extension of input file missing");
+/// The exit code used when the program failed to run
+const RUNTIME_ERROR_EXIT_CODE: i32 = 1;
+
+/// The exit code used when the maximum number of cycles was executed before the program halted
+const CYCLE_LIMIT_EXIT_CODE: i32 = 3;
+
+/// Creates a tracer for the program, as requested by the arguments
+fn tracer<'a>(
+    program: &'a Program,
+    format: TraceFormat,
+    args: &Args,
+) -> Result<Tracer<'a>, String> {
+    let output: Box<dyn Write> = match args.trace_file.as_ref() {
+        Some(path) => {
+            Box::new(BufWriter::new(File::create(path).map_err(|error| {
+                format!("Failed to create trace file: {error}")
+            })?))
+        }
+        None => Box::new(io::stderr()),
     };
+    let tracer = Tracer::new(program, output, format);
+    match args.trace_range.as_deref() {
+        Some(range) => {
+            let range = label_range(program, range)
+                .map_err(|error| format!("Invalid trace range: {error}"))?;
+            Ok(tracer.with_range(range))
+        }
+        None => Ok(tracer),
+    }
+}
+
+/// Runs the program, reporting how it stopped.
+/// Returns the exit code of the compiler.
+fn run(program: &Program, args: &Args) -> i32 {
+    let mut vm = program.vm();
+    vm.set_stack_depth(args.stack_depth);
+    vm.set_stack_size(args.stack_size);
+    vm.set_memory_size(args.memory_size);
 
-    // Compile the code to bytecode or load existing bytecode.
-    // Only allow basm for code and basmo for bytecode
-    match extension.to_str() {
-        Some("basm") => {
-            // Load the code
-            let code = std::fs::read_to_string(&args.file).expect("Input file doesn't exist");
+    // Trace the executed instructions, if requested
+    if let Some(format) = args.trace {
+        match tracer(program, format.into(), args) {
+            Ok(tracer) => vm.set_tracer(tracer),
+            Err(error) => {
+                eprintln!("error: {error}");
+                return RUNTIME_ERROR_EXIT_CODE;
+            }
+        }
+    }
+
+    // Continue from the snapshot, if requested
+    if let Some(path) = args.resume.as_ref() {
+        if let Err(error) = Snapshot::load(path).and_then(|snapshot| snapshot.restore(&mut vm)) {
+            eprintln!("error: Failed to resume from snapshot: {error}");
+            return RUNTIME_ERROR_EXIT_CODE;
+        }
+    }
+
+    let result = vm
+        .execute(args.cycles)
+        .map_err(|error| program.locate(error));
 
-            // Compile the code by splitting it into tokens
-            compile::split_tokens(&code).unwrap()
+    // Store the state if the program can be resumed and a snapshot was requested
+    if let Some(path) = args.snapshot.as_ref().filter(|_| !vm.is_halted()) {
+        if let Err(error) = Snapshot::new(&vm).save(path) {
+            eprintln!("error: Failed to store snapshot: {error}");
         }
-        Some("basmo") => {
-            // Read the existing bytecode
-            let code = std::fs::read(&args.file).expect("Input file doesn't exist");
+    }
 
-            // Convert it to tokens
-            postcard::from_bytes::<Vec<Token>>(&code).expect("Invalid basmo input file.")
+    match result {
+        Ok(state) => {
+            // Only report how the program stopped if it could have been cut off
+            if args.cycles.is_some() {
+                eprintln!("Program {state}");
+            }
+            0
+        }
+        Err(error) if matches!(error.kind(), RuntimeErrorKind::CycleLimitExceeded(_)) => {
+            eprintln!("error: {error}");
+            CYCLE_LIMIT_EXIT_CODE
         }
-        _ => {
-            // Invalid input file was used
-            panic!("Invalid input file!");
+        Err(error) => {
+            eprintln!("error: {error}");
+            RUNTIME_ERROR_EXIT_CODE
         }
     }
 }
@@ -80,7 +405,7 @@ fn print_performance(
 
     // Print compilation performance, if the code was compiled
     if args
-        .file
+        .file()
         .extension()
         .is_some_and(|extension| extension == "basm")
     {
@@ -98,6 +423,118 @@ fn print_performance(
     }
 }
 
+/// Loads the program, printing the errors and exiting on failure
+fn load(file: &Path, args: &Args) -> Program {
+    match Program::load(file) {
+        Ok(program) => program,
+        Err(error) => {
+            print_load_error(&error, file, args);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Debugs the program interactively, reading commands from stdin
+fn debug(file: &Path, args: &Args) {
+    let program = load(file, args);
+
+    // Read the code of bytecode from the file in its source map, if it was stored
+    let source_file = if file
+        .extension()
+        .is_some_and(|extension| extension == "basm")
+    {
+        Some(file)
+    } else {
+        program.source_map().file().map(Path::new)
+    };
+    let source = source_file.and_then(|file| std::fs::read_to_string(file).ok());
+    let mut debugger = Debugger::new(&program, source.as_deref())
+        .with_stack_depth(args.stack_depth)
+        .with_stack_size(args.stack_size)
+        .with_memory_size(args.memory_size);
+    if let Err(error) = debugger.run(io::stdin().lock(), io::stderr()) {
+        eprintln!("error: {error}");
+        std::process::exit(1);
+    }
+}
+
+/// Runs a Debug Adapter Protocol server over stdin and stdout
+fn dap() {
+    if let Err(error) = dap::serve(BufReader::new(io::stdin()), io::stdout().lock()) {
+        eprintln!("error: {error}");
+        std::process::exit(1);
+    }
+}
+
+/// Runs a Language Server Protocol server over stdin and stdout
+fn lsp() {
+    if let Err(error) = lsp::serve(io::stdin().lock(), io::stdout().lock()) {
+        eprintln!("error: {error}");
+        std::process::exit(1);
+    }
+}
+
+/// Formats the files, or checks whether they are formatted.
+/// Returns exit code 1 if a file couldn't be formatted or, when checking, isn't formatted.
+fn fmt(files: &[PathBuf], check: bool, args: &Args) -> i32 {
+    let mut failed = false;
+    for file in files {
+        let formatted = std::fs::read_to_string(file)
+            .map_err(Error::from)
+            .and_then(|code| Ok((format(&code)?, code)));
+        match formatted {
+            Ok((formatted, code)) if formatted == code => {}
+            Ok(_) if check => {
+                eprintln!("{} is not formatted", file.display());
+                failed = true;
+            }
+            Ok((formatted, _)) => {
+                if let Err(error) = std::fs::write(file, formatted) {
+                    eprintln!("error: Failed to write {}: {error}", file.display());
+                    failed = true;
+                }
+            }
+            Err(error) => {
+                print_load_error(&error, file, args);
+                failed = true;
+            }
+        }
+    }
+    i32::from(failed)
+}
+
+/// Disassembles the program, writing the code to the output file or stdout.
+/// Warns if reassembling the code doesn't result in the same bytecode.
+fn disasm(file: &Path, out: Option<&Path>, args: &Args) {
+    let program = load(file, args);
+    let code = disassemble(&program);
+
+    // Check whether the code assembles to the same bytecode, stripping it like the program was
+    let stripped = !program.tokens().iter().any(Token::is_no_op);
+    let same = Program::assemble(&code)
+        .map_err(Error::from)
+        .map(|reassembled| {
+            if stripped {
+                reassembled.strip()
+            } else {
+                reassembled
+            }
+        })
+        .and_then(|reassembled| Ok(reassembled.to_bytes()? == program.to_bytes()?));
+    if !matches!(same, Ok(true)) {
+        eprintln!("warning: reassembling the code won't result in the same bytecode");
+    }
+
+    let result = match out {
+        Some(out) => std::fs::write(out, code),
+        None => io::stdout().write_all(code.as_bytes()),
+    };
+    if let Err(error) = result {
+        eprintln!("error: Failed to write the code: {error}");
+        std::process::exit(1);
+    }
+}
+
 fn main() {
     // Start measuring performance
     let start = Instant::now();
@@ -106,37 +543,98 @@ fn main() {
     let args = Args::parse();
     let parsing = Instant::now();
 
+    // Run the subcommand instead, if one was used
+    match args.command.as_ref() {
+        Some(Command::Debug { file }) => return debug(file, &args),
+        Some(Command::Dap) => return dap(),
+        Some(Command::Lsp) => return lsp(),
+        Some(Command::Fmt { files, check }) => std::process::exit(fmt(files, *check, &args)),
+        Some(Command::Disasm { file, out }) => return disasm(file, out.as_deref(), &args),
+        None => {}
+    }
+
     // Make sure the code is run or the bytecode is saved
-    assert!(
-        args.out.is_some() || args.run,
-        "The bytecode should be run and/or saved!"
-    );
+    if args.out.is_none() && !args.run {
+        eprintln!("error: The bytecode should be run with -r and/or saved with -o");
+        std::process::exit(1);
+    }
 
-    let mut tokens = load_bytecode(&args);
-    compile::convert_labels(&mut tokens);
+    // Compile the code or load the existing bytecode
+    let program = load(args.file(), &args);
+    let program = if args.strip { program.strip() } else { program };
     let compiling = Instant::now();
 
     // Run the code if requested
-    if args.run {
-        execute::execute(&tokens, args.cycles);
-    }
+    // Remember the exit code, to exit after storing the bytecode
+    let exit_code = if args.run { run(&program, &args) } else { 0 };
     let executing = Instant::now();
 
     // Save the bytecode if requested
     if let Some(output) = args.out.as_ref() {
-        let file = File::create(output).expect("Failed to create output file");
-        let output = BufWriter::new(file);
-        postcard::to_io(&tokens, output).expect("Failed to write tokens to output file");
+        let options = SaveOptions {
+            encoding: args.encoding.into(),
+            debug_info: args.debug_info,
+        };
+        if let Err(error) = program.save_with(output, &options) {
+            eprintln!("error: Failed to write tokens to output file: {error}");
+            std::process::exit(1);
+        }
     }
     let storing = Instant::now();
 
     // Print the tokens if requested
     if args.debug {
-        eprintln!("{tokens:?}");
+        eprintln!("{:?}", program.tokens());
     }
 
     // Test performance if requested
     if args.performance {
         print_performance(start, parsing, compiling, executing, storing, &args);
     }
+
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fmt, run, Args, CYCLE_LIMIT_EXIT_CODE, RUNTIME_ERROR_EXIT_CODE};
+    use byte_assembly::Program;
+    use clap::Parser;
+
+    /// Runs the code with the arguments, returning the exit code
+    fn exit_code(code: &str, arguments: &[&str]) -> i32 {
+        let program = Program::assemble(code).unwrap();
+        let args = Args::parse_from(["byte_assembly", "-r", "code.basm"].iter().chain(arguments));
+        run(&program, &args)
+    }
+
+    #[test]
+    fn exits_with_the_reason_the_program_stopped() {
+        let endless = "loop:\njmp loop";
+        assert_eq!(exit_code("mov r0, 1", &[]), 0);
+        assert_eq!(exit_code("div r0, 1, 0", &[]), RUNTIME_ERROR_EXIT_CODE);
+        assert_eq!(exit_code(endless, &["-c", "100"]), CYCLE_LIMIT_EXIT_CODE);
+        assert_eq!(CYCLE_LIMIT_EXIT_CODE, 3);
+    }
+
+    #[test]
+    fn checks_whether_files_are_formatted() {
+        let path = std::env::temp_dir().join(format!("fmt_{}.basm", std::process::id()));
+        let files = [path.clone()];
+        let args = Args::parse_from(["byte_assembly", "fmt", "code.basm"]);
+        std::fs::write(&path, "mov r0,1").unwrap();
+
+        // Checking doesn't change the file
+        assert_eq!(fmt(&files, true, &args), 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "mov r0,1");
+        assert_eq!(fmt(&files, false, &args), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "    mov r0, 1\n");
+        assert_eq!(fmt(&files, true, &args), 0);
+
+        std::fs::write(&path, "mvo r0, 1").unwrap();
+        assert_eq!(fmt(&files, true, &args), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
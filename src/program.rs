@@ -0,0 +1,648 @@
+use crate::{
+    compile::{self, error::Error as CompileError, span::Span},
+    execute::{error::RuntimeError, ExitState, Vm, MAX_MEMORY_SIZE},
+    token::Label,
+    Token,
+};
+use container::{Container, ContainerError, SectionKind};
+use source_map::SourceMap;
+use std::{
+    fmt::{self, Display},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+use verify::VerifyError;
+
+pub mod compact;
+pub mod container;
+pub mod source_map;
+pub mod verify;
+
+/// An error that occurred while loading or storing a program
+#[derive(Debug)]
+pub enum Error {
+    /// The file couldn't be read or written
+    Io(io::Error),
+
+    /// The code couldn't be compiled, contains every error found in the code
+    Compile(Vec<CompileError>),
+
+    /// The bytecode couldn't be encoded or decoded
+    Bytecode(postcard::Error),
+
+    /// The compact bytecode couldn't be encoded or decoded
+    Compact(compact::Error),
+
+    /// The header or layout of the bytecode file is invalid
+    Container(ContainerError),
+
+    /// The program can't be executed, contains every problem found by the verifier
+    Verify(Vec<VerifyError>),
+
+    /// The debug info has a location for a different number of tokens than the program has,
+    /// or a label after the end of the program
+    InvalidDebugInfo,
+
+    /// The data section of the bytecode, of the contained number of bytes, doesn't fit in memory
+    DataTooLarge(usize),
+
+    /// The file doesn't end with `.basm` or `.basmo`
+    InvalidExtension,
+}
+
+/// Writes every error on its own line
+fn write_lines(f: &mut fmt::Formatter<'_>, errors: &[impl Display]) -> fmt::Result {
+    for (index, error) in errors.iter().enumerate() {
+        if index > 0 {
+            writeln!(f)?;
+        }
+        write!(f, "{error}")?;
+    }
+    Ok(())
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{error}"),
+            Self::Compile(errors) => write_lines(f, errors),
+            Self::Verify(errors) => write_lines(f, errors),
+            Self::Bytecode(error) => write!(f, "Invalid basmo file: {error}"),
+            Self::Compact(error) => write!(f, "{error}"),
+            Self::Container(error) => write!(f, "{error}"),
+            Self::InvalidDebugInfo => {
+                write!(
+                    f,
+                    "Invalid basmo file: the debug info doesn't match the program"
+                )
+            }
+            Self::DataTooLarge(size) => write!(
+                f,
+                "The data section of {size} bytes doesn't fit in memory, \
+                which holds at most {MAX_MEMORY_SIZE} bytes"
+            ),
+            Self::InvalidExtension => write!(f, "Invalid input file, expected .basm or .basmo"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Compile(errors) => errors
+                .first()
+                .map(|error| error as &(dyn std::error::Error + 'static)),
+            Self::Bytecode(error) => Some(error),
+            Self::Compact(error) => Some(error),
+            Self::Container(error) => Some(error),
+            Self::Verify(errors) => errors
+                .first()
+                .map(|error| error as &(dyn std::error::Error + 'static)),
+            Self::InvalidDebugInfo | Self::DataTooLarge(_) | Self::InvalidExtension => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<Vec<CompileError>> for Error {
+    fn from(errors: Vec<CompileError>) -> Self {
+        Self::Compile(errors)
+    }
+}
+
+impl From<postcard::Error> for Error {
+    fn from(error: postcard::Error) -> Self {
+        Self::Bytecode(error)
+    }
+}
+
+impl From<Vec<VerifyError>> for Error {
+    fn from(errors: Vec<VerifyError>) -> Self {
+        Self::Verify(errors)
+    }
+}
+
+impl From<compact::Error> for Error {
+    fn from(error: compact::Error) -> Self {
+        Self::Compact(error)
+    }
+}
+
+impl From<ContainerError> for Error {
+    fn from(error: ContainerError) -> Self {
+        Self::Container(error)
+    }
+}
+
+/// The options used when running a program
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// The maximum number of cycles to run, unlimited if `None`
+    pub cycles: Option<usize>,
+
+    /// The maximum number of nested calls,
+    /// [`DEFAULT_STACK_DEPTH`](crate::execute::DEFAULT_STACK_DEPTH) if `None`
+    pub stack_depth: Option<usize>,
+
+    /// The maximum number of values on the data stack,
+    /// [`DEFAULT_STACK_SIZE`](crate::execute::DEFAULT_STACK_SIZE) if `None`
+    pub stack_size: Option<usize>,
+
+    /// The number of bytes of memory,
+    /// [`DEFAULT_MEMORY_SIZE`](crate::execute::DEFAULT_MEMORY_SIZE) if `None`
+    pub memory_size: Option<usize>,
+}
+
+/// How the tokens are encoded in `.basmo` bytecode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// The tokens serialized with postcard
+    #[default]
+    Postcard,
+
+    /// Fixed-width instructions with pools for numbers and strings, see [`compact::encode`]
+    Compact,
+}
+
+/// The options used when storing a program as bytecode
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SaveOptions {
+    /// How the tokens are encoded
+    pub encoding: Encoding,
+
+    /// Whether to store the names of removed labels and the locations of the tokens in the code
+    pub debug_info: bool,
+}
+
+/// The content of the debug info section: the names and addresses of removed labels,
+/// and the source map
+type DebugInfo = (Vec<(String, usize)>, SourceMap);
+
+/// A compiled program, ready to be run or stored as bytecode
+#[derive(Debug)]
+pub struct Program {
+    tokens: Vec<Token>,
+    data: Vec<u8>,
+    source_map: SourceMap,
+    labels: Vec<(String, usize)>,
+}
+
+impl Program {
+    /// Creates a program from tokens, converting labels to addresses.
+    /// The data section is laid out and separated from the instructions.
+    #[must_use]
+    pub fn from_tokens(tokens: Vec<Token>) -> Self {
+        let (mut tokens, data) = compile::split_data(tokens);
+        compile::convert_labels(&mut tokens);
+        Self {
+            tokens,
+            data,
+            source_map: SourceMap::default(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Compiles code to a program, recording the location of every token in the source map
+    ///
+    /// # Errors
+    /// Returns every error found in the code, if it isn't valid byte assembly.
+    pub fn assemble(code: &str) -> Result<Self, Vec<CompileError>> {
+        let (tokens, mut spans): (Vec<_>, Vec<_>) =
+            compile::split_spanned_tokens(code)?.into_iter().unzip();
+
+        // Only instructions are executed, so the data section isn't in the source map
+        let mut in_data = compile::data_tokens(&tokens).into_iter();
+        spans.retain(|_| !in_data.next().unwrap_or_default());
+        Ok(Self {
+            source_map: SourceMap::new(None, spans),
+            ..Self::from_tokens(tokens)
+        })
+    }
+
+    /// Decodes a program from `.basmo` bytecode in either encoding, and verifies it.
+    /// Label names and locations in the code are restored from the debug info, if it is stored.
+    ///
+    /// # Errors
+    /// Returns an error if the bytecode was written by another version, is corrupted or invalid,
+    /// or contains instructions that can't be executed.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let container = Container::from_bytes(bytes)?;
+        let tokens = match container.section(SectionKind::CompactCode) {
+            Some(code) => compact::decode(code)?,
+            None => postcard::from_bytes(container.required_section(SectionKind::Code)?)?,
+        };
+        let mut program = Self::from_tokens(tokens);
+        if let Some(data) = container.section(SectionKind::Data) {
+            if data.len() > MAX_MEMORY_SIZE {
+                return Err(Error::DataTooLarge(data.len()));
+            }
+            program.data = data.to_vec();
+        }
+        if let Some(debug_info) = container.section(SectionKind::DebugInfo) {
+            (program.labels, program.source_map) = postcard::from_bytes::<DebugInfo>(debug_info)?;
+
+            // The source map and labels are indexed by the addresses of the tokens
+            let length = program.tokens.len();
+            let spans = program.source_map.spans().len();
+            if spans != 0 && spans != length
+                || program.labels.iter().any(|(_, address)| *address > length)
+            {
+                return Err(Error::InvalidDebugInfo);
+            }
+        }
+        program.verify()?;
+        Ok(program)
+    }
+
+    /// Encodes the program to `.basmo` bytecode, with the tokens encoded with postcard
+    /// and without debug info
+    ///
+    /// # Errors
+    /// Returns an error if the tokens couldn't be encoded.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.to_bytes_with(&SaveOptions::default())
+    }
+
+    /// Encodes the program to `.basmo` bytecode, with the tokens in the encoding of the options
+    /// and debug info if requested
+    ///
+    /// # Errors
+    /// Returns an error if the tokens couldn't be encoded.
+    pub fn to_bytes_with(&self, options: &SaveOptions) -> Result<Vec<u8>, Error> {
+        let mut container = Container::new();
+        match options.encoding {
+            Encoding::Postcard => {
+                container.set_section(SectionKind::Code, postcard::to_stdvec(&self.tokens)?);
+            }
+            Encoding::Compact => {
+                container.set_section(SectionKind::CompactCode, compact::encode(&self.tokens)?);
+            }
+        }
+        if !self.data.is_empty() {
+            container.set_section(SectionKind::Data, self.data.clone());
+        }
+        if options.debug_info {
+            let debug_info = postcard::to_stdvec(&(&self.labels, &self.source_map))?;
+            container.set_section(SectionKind::DebugInfo, debug_info);
+        }
+        Ok(container.to_bytes())
+    }
+
+    /// Loads a program from a `.basm` code file or a `.basmo` bytecode file, and verifies it
+    ///
+    /// # Errors
+    /// Returns an error if the file couldn't be read, has an invalid extension,
+    /// or doesn't contain a valid program.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        // Compile the code to bytecode or load existing bytecode.
+        // Only allow basm for code and basmo for bytecode
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("basm") => {
+                let mut program = Self::assemble(&std::fs::read_to_string(path)?)?;
+                program
+                    .source_map
+                    .set_file(Some(path.display().to_string()));
+                program.verify()?;
+                Ok(program)
+            }
+            Some("basmo") => Self::from_bytes(&std::fs::read(path)?),
+            _ => Err(Error::InvalidExtension),
+        }
+    }
+
+    /// Writes the program as `.basmo` bytecode
+    ///
+    /// # Errors
+    /// Returns an error if the tokens couldn't be encoded or written.
+    pub fn write_to(&self, mut output: impl Write) -> Result<(), Error> {
+        output.write_all(&self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Stores the program as a `.basmo` bytecode file
+    ///
+    /// # Errors
+    /// Returns an error if the file couldn't be created or written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        self.save_with(path, &SaveOptions::default())
+    }
+
+    /// Stores the program as a `.basmo` bytecode file, see [`Program::to_bytes_with`]
+    ///
+    /// # Errors
+    /// Returns an error if the tokens couldn't be encoded, or the file couldn't be created
+    /// or written.
+    pub fn save_with(&self, path: impl AsRef<Path>, options: &SaveOptions) -> Result<(), Error> {
+        let mut output = BufWriter::new(File::create(path)?);
+        output.write_all(&self.to_bytes_with(options)?)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Returns the tokens of the program
+    #[must_use]
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Returns the data section, which is loaded into memory at address 0 before running
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the location in the code of the token at the index.
+    /// Only known for programs compiled from code, or loaded with debug info.
+    #[must_use]
+    pub fn span(&self, index: usize) -> Option<Span> {
+        self.source_map.span(index)
+    }
+
+    /// Returns whether the locations in the code of the tokens are known
+    #[must_use]
+    pub const fn has_spans(&self) -> bool {
+        !self.source_map.is_empty()
+    }
+
+    /// Returns the source map, mapping the tokens to their location in the code
+    #[must_use]
+    pub const fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    /// Returns the names and addresses of the labels removed by [`Program::strip`]
+    #[must_use]
+    pub fn labels(&self) -> &[(String, usize)] {
+        &self.labels
+    }
+
+    /// Returns the address execution continues at after jumping to the label,
+    /// looking up removed labels as well
+    #[must_use]
+    pub fn label(&self, name: &str) -> Option<usize> {
+        self.tokens
+            .iter()
+            .position(|token| matches!(token, Token::Label(label) if label == name))
+            .map(|index| index + 1)
+            .or_else(|| {
+                self.labels
+                    .iter()
+                    .find(|(label, _)| label == name)
+                    .map(|(_, address)| *address)
+            })
+    }
+
+    /// Removes comments and labels, which don't do anything when executed,
+    /// and updates the addresses jumped to.
+    /// The names and new addresses of the labels are kept, to be stored as debug info,
+    /// as are the locations in the code of the remaining tokens.
+    #[must_use]
+    pub fn strip(self) -> Self {
+        let Self {
+            tokens,
+            data,
+            mut source_map,
+            mut labels,
+        } = self;
+        source_map.retain(|index| !tokens[index].is_no_op());
+
+        // Calculate the new address of every address, including the end of the program
+        let mut addresses = Vec::with_capacity(tokens.len() + 1);
+        let mut kept = 0;
+        for token in &tokens {
+            addresses.push(kept);
+            if !token.is_no_op() {
+                kept += 1;
+            }
+        }
+        addresses.push(kept);
+        let remap = |label| match label {
+            Label::Address(address) => {
+                Label::Address(addresses.get(address).copied().unwrap_or(address))
+            }
+            Label::Base(_) => label,
+        };
+        for (_, address) in &mut labels {
+            *address = addresses.get(*address).copied().unwrap_or(*address);
+        }
+
+        let mut stripped = Vec::with_capacity(kept);
+        for (index, token) in tokens.into_iter().enumerate() {
+            let token = match token {
+                Token::Comment(_) => continue,
+                Token::Label(name) => {
+                    labels.push((name, addresses[index]));
+                    continue;
+                }
+                Token::Jmp(label) => Token::Jmp(remap(label)),
+                Token::Jl(label) => Token::Jl(remap(label)),
+                Token::Jg(label) => Token::Jg(remap(label)),
+                Token::Je(label) => Token::Je(remap(label)),
+                Token::Call(label) => Token::Call(remap(label)),
+                token => token,
+            };
+            stripped.push(token);
+        }
+        Self {
+            tokens: stripped,
+            data,
+            source_map,
+            labels,
+        }
+    }
+
+    /// Checks the program can be executed, see [`verify::verify`]
+    ///
+    /// # Errors
+    /// Returns every problem found, with its location in the code if it is known.
+    pub fn verify(&self) -> Result<(), Vec<VerifyError>> {
+        verify::verify(&self.tokens, self.data.len()).map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|error| {
+                    let location = self.span(error.index());
+                    error.with_location(location)
+                })
+                .collect()
+        })
+    }
+
+    /// Runs the program
+    ///
+    /// # Errors
+    /// Returns an error if an instruction failed or the maximum number of cycles was executed
+    /// before the program halted.
+    pub fn run(&self, options: &RunOptions) -> Result<ExitState, RuntimeError> {
+        let mut vm = self.vm();
+        if let Some(depth) = options.stack_depth {
+            vm.set_stack_depth(depth);
+        }
+        if let Some(size) = options.stack_size {
+            vm.set_stack_size(size);
+        }
+        if let Some(size) = options.memory_size {
+            vm.set_memory_size(size);
+        }
+        vm.execute(options.cycles)
+            .map_err(|error| self.locate(error))
+    }
+
+    /// Creates a virtual machine executing the program, writing output to stdout.
+    /// The data section is loaded into memory.
+    #[must_use]
+    pub fn vm(&self) -> Vm<'_> {
+        let mut vm = Vm::new(&self.tokens);
+        vm.load_data(&self.data);
+        vm
+    }
+
+    /// Adds the file and location in the code of the failed instruction to the error,
+    /// if they are known
+    #[must_use]
+    pub fn locate(&self, error: RuntimeError) -> RuntimeError {
+        let location = self.span(error.pc());
+        let file = self.source_map.file().map(str::to_owned);
+        error.with_location(location).with_file(file)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{
+        container::{Container, SectionKind},
+        source_map::SourceMap,
+        Error, Program, SaveOptions,
+    };
+    use crate::{compile::span::Span, execute::MAX_MEMORY_SIZE};
+    use std::path::{Path, PathBuf};
+
+    /// Returns the paths of the sample programs in the tests directory and its subdirectories
+    fn sample_paths(directory: &Path) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                paths.extend(sample_paths(&path));
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension == "basm")
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        paths
+    }
+
+    /// Assembles the sample programs, skipping those that aren't valid on purpose
+    pub fn samples() -> Vec<(PathBuf, Program)> {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let samples = sample_paths(&directory)
+            .into_iter()
+            .filter_map(|path| Some((path.clone(), Program::load(path).ok()?)))
+            .collect::<Vec<_>>();
+        assert!(samples.len() > 10);
+        samples
+    }
+
+    /// Returns the bytecode of the code, with the data section replaced by the data
+    fn with_data(code: &str, data: Vec<u8>) -> Vec<u8> {
+        let bytes = Program::assemble(code).unwrap().to_bytes().unwrap();
+        let mut container = Container::from_bytes(&bytes).unwrap();
+        container.set_section(SectionKind::Data, data);
+        container.to_bytes()
+    }
+
+    /// Returns the bytecode of the code with debug info, replaced by the labels and spans
+    fn with_debug_info(code: &str, labels: &[(&str, usize)], spans: &[Span]) -> Vec<u8> {
+        let options = SaveOptions {
+            debug_info: true,
+            ..SaveOptions::default()
+        };
+        let bytes = Program::assemble(code)
+            .unwrap()
+            .to_bytes_with(&options)
+            .unwrap();
+        let mut container = Container::from_bytes(&bytes).unwrap();
+        let labels = labels
+            .iter()
+            .map(|(name, address)| ((*name).to_owned(), *address))
+            .collect::<Vec<_>>();
+        let source_map = SourceMap::new(None, spans.to_vec());
+        let debug_info = postcard::to_stdvec(&(labels, source_map)).unwrap();
+        container.set_section(SectionKind::DebugInfo, debug_info);
+        container.to_bytes()
+    }
+
+    #[test]
+    fn rejects_debug_info_of_other_programs() {
+        let code = "start:\nmov r0, 1\njmp start";
+        let span = Span::default();
+        let valid = with_debug_info(code, &[("end", 3)], &[span; 3]);
+        let program = Program::from_bytes(&valid).unwrap();
+        assert_eq!(program.strip().tokens().len(), 2);
+        assert!(Program::from_bytes(&with_debug_info(code, &[], &[])).is_ok());
+
+        for invalid in [
+            with_debug_info(code, &[], &[span; 4]),
+            with_debug_info(code, &[], &[span; 2]),
+            with_debug_info(code, &[("end", 4)], &[span; 3]),
+        ] {
+            assert!(matches!(
+                Program::from_bytes(&invalid),
+                Err(Error::InvalidDebugInfo)
+            ));
+        }
+    }
+
+    #[test]
+    fn loads_the_data_section() {
+        let bytes = with_data("load r0, [0]", vec![7; MAX_MEMORY_SIZE]);
+        let program = Program::from_bytes(&bytes).unwrap();
+        assert_eq!(program.data().len(), MAX_MEMORY_SIZE);
+    }
+
+    #[test]
+    fn rejects_data_sections_larger_than_the_memory() {
+        let bytes = with_data("mov r0, 1", vec![0; MAX_MEMORY_SIZE + 1]);
+        assert!(matches!(
+            Program::from_bytes(&bytes),
+            Err(Error::DataTooLarge(size)) if size == MAX_MEMORY_SIZE + 1
+        ));
+    }
+
+    #[test]
+    fn maps_stripped_instructions_to_the_code() {
+        let path = std::env::temp_dir().join(format!("map_{}.basm", std::process::id()));
+        let code = "/* Doubles r0 */\nmov r0, 2\nloop:\n    /* twice */ add r0, r0, r0\n\
+            cmp r0, 16\njl loop\nout \"{0}\"\n";
+        std::fs::write(&path, code).unwrap();
+        let program = Program::load(&path).unwrap().strip();
+        std::fs::remove_file(&path).unwrap();
+
+        // The comments and the label are gone, the add and out instructions are at 1 and 4
+        let file = path.display();
+        let location =
+            |program: &Program, index| program.source_map().location(index).unwrap().to_string();
+        assert_eq!(location(&program, 1), format!("{file}:4:17"));
+        assert_eq!(location(&program, 4), format!("{file}:7:1"));
+
+        // The locations are kept in the debug info
+        let options = SaveOptions {
+            debug_info: true,
+            ..SaveOptions::default()
+        };
+        let bytes = program.to_bytes_with(&options).unwrap();
+        let loaded = Program::from_bytes(&bytes).unwrap();
+        assert_eq!(location(&loaded, 4), location(&program, 4));
+    }
+}
@@ -0,0 +1,683 @@
+use crate::{
+    token::Label,
+    value::{Address, Value},
+    Token,
+};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+/// The size of an encoded instruction
+pub const INSTRUCTION_SIZE: usize = 8;
+
+/// The size of the header of the code: instruction, immediate and string pool sizes
+const HEADER_SIZE: usize = 12;
+
+/// The kinds of operands, stored in 2 bits per operand.
+/// The bits of the first operand are the lowest, followed by those of the second operand.
+mod kind {
+    /// The operand is a register
+    pub const REGISTER: u8 = 0;
+
+    /// The operand is an index into the immediate pool.
+    /// For jumps, the target is the offset of a label name in the string pool.
+    pub const POOL: u8 = 1;
+
+    /// The operand is a number that fits in 16 bits
+    pub const INLINE: u8 = 2;
+
+    /// The mask of the bits of an operand
+    pub const MASK: u8 = 0b11;
+
+    /// The shift of the bits of the second operand
+    pub const SECOND: u8 = 2;
+
+    /// Set for memory accesses using a base register, which is stored in the register byte
+    pub const BASE: u8 = 1 << 4;
+}
+
+/// The opcodes of the instructions
+mod opcode {
+    pub const COMMENT: u8 = 0;
+    pub const OUT: u8 = 1;
+    pub const MOV: u8 = 2;
+    pub const ADD: u8 = 3;
+    pub const SUB: u8 = 4;
+    pub const MUL: u8 = 5;
+    pub const DIV: u8 = 6;
+    pub const MOD: u8 = 7;
+    pub const LABEL: u8 = 8;
+    pub const JMP: u8 = 9;
+    pub const JL: u8 = 10;
+    pub const JG: u8 = 11;
+    pub const JE: u8 = 12;
+    pub const CMP: u8 = 13;
+    pub const AND: u8 = 14;
+    pub const OR: u8 = 15;
+    pub const XOR: u8 = 16;
+    pub const NOT: u8 = 17;
+    pub const SHL: u8 = 18;
+    pub const SHR: u8 = 19;
+    pub const SAR: u8 = 20;
+    pub const ROL: u8 = 21;
+    pub const ROR: u8 = 22;
+    pub const CALL: u8 = 23;
+    pub const RET: u8 = 24;
+    pub const PUSH: u8 = 25;
+    pub const POP: u8 = 26;
+    pub const PEEK: u8 = 27;
+    pub const LOAD: u8 = 28;
+    pub const STORE: u8 = 29;
+}
+
+/// An error that occurred while encoding or decoding compact bytecode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The program has too many distinct numbers for the 16-bit operands
+    TooManyImmediates,
+
+    /// The program or its strings don't fit in the 32-bit sizes and offsets
+    TooLarge,
+
+    /// The bytecode ends before the instructions, immediates or strings do
+    Truncated,
+
+    /// The instruction at the index has an opcode that doesn't exist
+    UnknownOpcode { index: usize, opcode: u8 },
+
+    /// The instruction at the index refers to an immediate or string that doesn't exist
+    InvalidOperand(usize),
+
+    /// The tokens contain data directives or addresses of labels, which are only used
+    /// while assembling
+    Unassembled,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManyImmediates => write!(f, "Program uses more than 65536 different numbers"),
+            Self::TooLarge => write!(f, "Program is too large for compact bytecode"),
+            Self::Truncated => write!(f, "Compact bytecode is truncated"),
+            Self::UnknownOpcode { index, opcode } => {
+                write!(f, "Unknown opcode {opcode} at instruction {index}")
+            }
+            Self::InvalidOperand(index) => write!(f, "Invalid operand at instruction {index}"),
+            Self::Unassembled => write!(f, "Program has to be assembled before encoding it"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Converts a size or offset to 32 bits
+fn to_u32(value: usize) -> Result<u32, Error> {
+    u32::try_from(value).map_err(|_| Error::TooLarge)
+}
+
+/// Collects the numbers and strings of the instructions into pools while encoding
+#[derive(Default)]
+struct Pools {
+    immediates: Vec<i64>,
+    immediate_indices: HashMap<i64, u16>,
+    strings: Vec<u8>,
+    string_offsets: HashMap<String, u32>,
+}
+
+impl Pools {
+    /// Returns the operand kind and operand of a value
+    fn value(&mut self, value: &Value) -> Result<(u8, u16), Error> {
+        match value {
+            Value::Register(register) => Ok((kind::REGISTER, u16::from(*register))),
+            Value::Number(number) => {
+                if let Ok(number) = i16::try_from(*number) {
+                    return Ok((kind::INLINE, number.cast_unsigned()));
+                }
+                if let Some(index) = self.immediate_indices.get(number) {
+                    return Ok((kind::POOL, *index));
+                }
+                let index =
+                    u16::try_from(self.immediates.len()).map_err(|_| Error::TooManyImmediates)?;
+                self.immediates.push(*number);
+                self.immediate_indices.insert(*number, index);
+                Ok((kind::POOL, index))
+            }
+        }
+    }
+
+    /// Returns the offset of the string in the string pool.
+    /// Strings are stored as their length in bytes (u32), followed by the UTF-8 bytes.
+    fn string(&mut self, string: &str) -> Result<u32, Error> {
+        if let Some(offset) = self.string_offsets.get(string) {
+            return Ok(*offset);
+        }
+        let offset = to_u32(self.strings.len())?;
+        self.strings
+            .extend_from_slice(&to_u32(string.len())?.to_le_bytes());
+        self.strings.extend_from_slice(string.as_bytes());
+        self.string_offsets.insert(string.to_owned(), offset);
+        Ok(offset)
+    }
+
+    /// Encodes an instruction with a string operand
+    fn string_instruction(&mut self, opcode: u8, string: &str) -> Result<[u8; 8], Error> {
+        Ok(wide_instruction(opcode, 0, self.string(string)?))
+    }
+
+    /// Encodes an instruction storing the result of an operation in a register
+    fn operation(
+        &mut self,
+        opcode: u8,
+        register: u8,
+        left: &Value,
+        right: &Value,
+    ) -> Result<[u8; 8], Error> {
+        let (left_kind, left) = self.value(left)?;
+        let (right_kind, right) = self.value(right)?;
+        let kinds = left_kind | right_kind << kind::SECOND;
+        let [left_low, left_high] = left.to_le_bytes();
+        let [right_low, right_high] = right.to_le_bytes();
+        Ok([
+            opcode, kinds, register, 0, left_low, left_high, right_low, right_high,
+        ])
+    }
+
+    /// Encodes a memory access: the base register of the address in the register byte,
+    /// the offset of the address as first operand and the value or register as second operand
+    fn memory(&mut self, opcode: u8, address: &Address, value: &Value) -> Result<[u8; 8], Error> {
+        if address.label.is_some() {
+            return Err(Error::Unassembled);
+        }
+        let mut instruction = self.operation(
+            opcode,
+            address.base.unwrap_or_default(),
+            &Value::Number(address.offset),
+            value,
+        )?;
+        if address.base.is_some() {
+            instruction[1] |= kind::BASE;
+        }
+        Ok(instruction)
+    }
+
+    /// Encodes a jump, the target is an address or the offset of the name of a label
+    fn jump(&mut self, opcode: u8, label: &Label) -> Result<[u8; 8], Error> {
+        Ok(match label {
+            Label::Address(address) => wide_instruction(opcode, 0, to_u32(*address)?),
+            Label::Base(name) => wide_instruction(opcode, kind::POOL, self.string(name)?),
+        })
+    }
+
+    /// Encodes a single instruction
+    fn instruction(&mut self, token: &Token) -> Result<[u8; 8], Error> {
+        match token {
+            Token::Comment(comment) => self.string_instruction(opcode::COMMENT, comment),
+            Token::Out(output) => self.string_instruction(opcode::OUT, output),
+            Token::Mov(register, value) => {
+                self.operation(opcode::MOV, *register, value, &Value::Register(0))
+            }
+            Token::Add(register, left, right) => {
+                self.operation(opcode::ADD, *register, left, right)
+            }
+            Token::Sub(register, left, right) => {
+                self.operation(opcode::SUB, *register, left, right)
+            }
+            Token::Mul(register, left, right) => {
+                self.operation(opcode::MUL, *register, left, right)
+            }
+            Token::Div(register, left, right) => {
+                self.operation(opcode::DIV, *register, left, right)
+            }
+            Token::Mod(register, left, right) => {
+                self.operation(opcode::MOD, *register, left, right)
+            }
+            Token::Label(name) => self.string_instruction(opcode::LABEL, name),
+            Token::Jmp(label) => self.jump(opcode::JMP, label),
+            Token::Jl(label) => self.jump(opcode::JL, label),
+            Token::Jg(label) => self.jump(opcode::JG, label),
+            Token::Je(label) => self.jump(opcode::JE, label),
+            Token::Call(label) => self.jump(opcode::CALL, label),
+            Token::Ret => Ok(wide_instruction(opcode::RET, 0, 0)),
+            Token::Push(value) => self.operation(opcode::PUSH, 0, value, &Value::Register(0)),
+            Token::Pop(register) => self.operation(
+                opcode::POP,
+                *register,
+                &Value::Register(0),
+                &Value::Register(0),
+            ),
+            Token::Peek(register, offset) => {
+                self.operation(opcode::PEEK, *register, offset, &Value::Register(0))
+            }
+            Token::Load(register, address) => {
+                self.memory(opcode::LOAD, address, &Value::Register(*register))
+            }
+            Token::Store(address, value) => self.memory(opcode::STORE, address, value),
+            Token::Directive(_) => Err(Error::Unassembled),
+            Token::Cmp(left, right) => self.operation(opcode::CMP, 0, left, right),
+            Token::And(register, left, right) => {
+                self.operation(opcode::AND, *register, left, right)
+            }
+            Token::Or(register, left, right) => self.operation(opcode::OR, *register, left, right),
+            Token::Xor(register, left, right) => {
+                self.operation(opcode::XOR, *register, left, right)
+            }
+            Token::Not(register, value) => {
+                self.operation(opcode::NOT, *register, value, &Value::Register(0))
+            }
+            Token::Shl(register, left, right) => {
+                self.operation(opcode::SHL, *register, left, right)
+            }
+            Token::Shr(register, left, right) => {
+                self.operation(opcode::SHR, *register, left, right)
+            }
+            Token::Sar(register, left, right) => {
+                self.operation(opcode::SAR, *register, left, right)
+            }
+            Token::Rol(register, left, right) => {
+                self.operation(opcode::ROL, *register, left, right)
+            }
+            Token::Ror(register, left, right) => {
+                self.operation(opcode::ROR, *register, left, right)
+            }
+        }
+    }
+}
+
+/// Encodes an instruction with a single 32-bit operand
+const fn wide_instruction(opcode: u8, kinds: u8, operand: u32) -> [u8; 8] {
+    let [a, b, c, d] = operand.to_le_bytes();
+    [opcode, kinds, 0, 0, a, b, c, d]
+}
+
+/// Encodes the tokens in the compact format
+///
+/// The code starts with the number of instructions, immediates and bytes in the string pool,
+/// each as little endian u32. It is followed by the instructions, the immediate pool with
+/// every distinct number as little endian i64, and the string pool.
+///
+/// Every instruction is 8 bytes: the opcode, the operand kind bits, the register the result
+/// is stored in, a reserved byte and the operands. Instructions with 2 operands use 2 little
+/// endian u16 operands, which are registers, numbers that fit in 16 bits or indices in the
+/// immediate pool depending on the operand kinds. Other instructions use a single u32 operand:
+/// the address of a jump or the offset of a string in the string pool. Memory accesses store
+/// the base register of the address in the register byte, marked by a bit in the operand kinds.
+///
+/// # Errors
+/// Returns an error if the program uses more than 65536 distinct numbers or is too large.
+pub fn encode(tokens: &[Token]) -> Result<Vec<u8>, Error> {
+    let mut pools = Pools::default();
+    let instructions = tokens
+        .iter()
+        .map(|token| pools.instruction(token))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut bytes = Vec::with_capacity(
+        HEADER_SIZE
+            + instructions.len() * INSTRUCTION_SIZE
+            + pools.immediates.len() * 8
+            + pools.strings.len(),
+    );
+    bytes.extend_from_slice(&to_u32(instructions.len())?.to_le_bytes());
+    bytes.extend_from_slice(&to_u32(pools.immediates.len())?.to_le_bytes());
+    bytes.extend_from_slice(&to_u32(pools.strings.len())?.to_le_bytes());
+    bytes.extend(instructions.into_iter().flatten());
+    bytes.extend(
+        pools
+            .immediates
+            .iter()
+            .flat_map(|number| number.to_le_bytes()),
+    );
+    bytes.extend_from_slice(&pools.strings);
+    Ok(bytes)
+}
+
+/// Compact bytecode, allowing random access to the instructions without decoding all of them
+#[derive(Debug, Clone, Copy)]
+pub struct Code<'a> {
+    instructions: &'a [u8],
+    immediates: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Code<'a> {
+    /// Reads the sizes of the parts of the code
+    ///
+    /// # Errors
+    /// Returns an error if the bytes are shorter than the sizes in the header.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        let size = |index: usize| {
+            bytes
+                .get(index * 4..index * 4 + 4)
+                .map(|size| u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize)
+                .ok_or(Error::Truncated)
+        };
+        let instructions = size(0)?
+            .checked_mul(INSTRUCTION_SIZE)
+            .ok_or(Error::Truncated)?;
+        let immediates = size(1)?.checked_mul(8).ok_or(Error::Truncated)?;
+        let strings = size(2)?;
+
+        // Split the bytes into the instructions and pools
+        let body = &bytes[HEADER_SIZE..];
+        let (instructions, body) = body
+            .split_at_checked(instructions)
+            .ok_or(Error::Truncated)?;
+        let (immediates, body) = body.split_at_checked(immediates).ok_or(Error::Truncated)?;
+        let strings = body.get(..strings).ok_or(Error::Truncated)?;
+        Ok(Self {
+            instructions,
+            immediates,
+            strings,
+        })
+    }
+
+    /// Returns the number of instructions
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.instructions.len() / INSTRUCTION_SIZE
+    }
+
+    /// Returns whether the code has no instructions
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    /// Reads an operand, which is a register or a number depending on the kind
+    fn value(&self, index: usize, kind: u8, operand: u16) -> Result<Value, Error> {
+        match kind {
+            kind::REGISTER => u8::try_from(operand)
+                .map(Value::Register)
+                .map_err(|_| Error::InvalidOperand(index)),
+            kind::INLINE => Ok(Value::Number(operand.cast_signed().into())),
+            kind::POOL => {
+                let start = usize::from(operand) * 8;
+                self.immediates
+                    .get(start..start + 8)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(|bytes| Value::Number(i64::from_le_bytes(bytes)))
+                    .ok_or(Error::InvalidOperand(index))
+            }
+            _ => Err(Error::InvalidOperand(index)),
+        }
+    }
+
+    /// Reads the string at the offset in the string pool
+    fn string(&self, index: usize, offset: u32) -> Result<String, Error> {
+        let offset = offset as usize;
+        let length = self
+            .strings
+            .get(offset..offset + 4)
+            .map(|length| u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize)
+            .ok_or(Error::InvalidOperand(index))?;
+        self.strings
+            .get(offset + 4..offset + 4 + length)
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+            .map(str::to_owned)
+            .ok_or(Error::InvalidOperand(index))
+    }
+
+    /// Decodes the instruction at the index
+    ///
+    /// # Errors
+    /// Returns an error if there is no instruction at the index, or it is invalid.
+    pub fn get(&self, index: usize) -> Result<Token, Error> {
+        let start = index * INSTRUCTION_SIZE;
+        let [opcode, kinds, register, _, a, b, c, d] = self
+            .instructions
+            .get(start..start + INSTRUCTION_SIZE)
+            .and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+            .ok_or(Error::Truncated)?;
+        let wide = u32::from_le_bytes([a, b, c, d]);
+        let left = || self.value(index, kinds & kind::MASK, u16::from_le_bytes([a, b]));
+        let right = || {
+            let kind = kinds >> kind::SECOND & kind::MASK;
+            self.value(index, kind, u16::from_le_bytes([c, d]))
+        };
+        let address = || -> Result<Address, Error> {
+            let Value::Number(offset) = left()? else {
+                return Err(Error::InvalidOperand(index));
+            };
+            let base = (kinds & kind::BASE != 0).then_some(register);
+            Ok(Address {
+                base,
+                label: None,
+                offset,
+            })
+        };
+        let label = || match kinds & kind::MASK {
+            kind::REGISTER => Ok(Label::Address(wide as usize)),
+            kind::POOL => self.string(index, wide).map(Label::Base),
+            _ => Err(Error::InvalidOperand(index)),
+        };
+        Ok(match opcode {
+            opcode::COMMENT => Token::Comment(self.string(index, wide)?),
+            opcode::OUT => Token::Out(self.string(index, wide)?),
+            opcode::MOV => Token::Mov(register, left()?),
+            opcode::ADD => Token::Add(register, left()?, right()?),
+            opcode::SUB => Token::Sub(register, left()?, right()?),
+            opcode::MUL => Token::Mul(register, left()?, right()?),
+            opcode::DIV => Token::Div(register, left()?, right()?),
+            opcode::MOD => Token::Mod(register, left()?, right()?),
+            opcode::LABEL => Token::Label(self.string(index, wide)?),
+            opcode::JMP => Token::Jmp(label()?),
+            opcode::JL => Token::Jl(label()?),
+            opcode::JG => Token::Jg(label()?),
+            opcode::JE => Token::Je(label()?),
+            opcode::CALL => Token::Call(label()?),
+            opcode::RET => Token::Ret,
+            opcode::PUSH => Token::Push(left()?),
+            opcode::POP => Token::Pop(register),
+            opcode::PEEK => Token::Peek(register, left()?),
+            opcode::LOAD => match right()? {
+                Value::Register(register) => Token::Load(register, address()?),
+                Value::Number(_) => return Err(Error::InvalidOperand(index)),
+            },
+            opcode::STORE => Token::Store(address()?, right()?),
+            opcode::CMP => Token::Cmp(left()?, right()?),
+            opcode::AND => Token::And(register, left()?, right()?),
+            opcode::OR => Token::Or(register, left()?, right()?),
+            opcode::XOR => Token::Xor(register, left()?, right()?),
+            opcode::NOT => Token::Not(register, left()?),
+            opcode::SHL => Token::Shl(register, left()?, right()?),
+            opcode::SHR => Token::Shr(register, left()?, right()?),
+            opcode::SAR => Token::Sar(register, left()?, right()?),
+            opcode::ROL => Token::Rol(register, left()?, right()?),
+            opcode::ROR => Token::Ror(register, left()?, right()?),
+            opcode => return Err(Error::UnknownOpcode { index, opcode }),
+        })
+    }
+}
+
+/// Decodes tokens from the compact format, see [`encode`]
+///
+/// # Errors
+/// Returns an error if the bytes are truncated or contain invalid instructions.
+pub fn decode(bytes: &[u8]) -> Result<Vec<Token>, Error> {
+    let code = Code::new(bytes)?;
+    (0..code.len()).map(|index| code.get(index)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, Code, Error, HEADER_SIZE, INSTRUCTION_SIZE};
+    use crate::{
+        program::tests::samples,
+        token::{directive::Directive, Label},
+        value::{Address, Value},
+        Token,
+    };
+
+    /// Returns the size of a pool stored in the header of the code
+    fn pool_size(bytes: &[u8], index: usize) -> u32 {
+        u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn round_trips_the_samples() {
+        for (path, program) in samples() {
+            for program in [program.strip(), crate::Program::load(&path).unwrap()] {
+                let bytes = encode(program.tokens()).unwrap();
+                assert_eq!(
+                    decode(&bytes).unwrap(),
+                    program.tokens(),
+                    "{}",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn stores_large_numbers_in_the_immediate_pool() {
+        let numbers = [
+            0,
+            -1,
+            i64::from(i16::MAX),
+            i64::from(i16::MIN),
+            i64::from(i16::MAX) + 1,
+            i64::from(i16::MIN) - 1,
+            i64::MAX,
+            i64::MIN,
+            i64::MAX,
+        ];
+        let tokens = numbers
+            .iter()
+            .map(|number| Token::Add(1, Value::Number(*number), Value::Register(255)))
+            .collect::<Vec<_>>();
+        let bytes = encode(&tokens).unwrap();
+
+        // Only the 4 distinct numbers that don't fit in 16 bits are pooled
+        assert_eq!(pool_size(&bytes, 1), 4);
+        assert_eq!(
+            bytes.len(),
+            HEADER_SIZE + tokens.len() * INSTRUCTION_SIZE + 4 * 8
+        );
+        assert_eq!(decode(&bytes).unwrap(), tokens);
+    }
+
+    #[test]
+    fn limits_the_immediate_pool() {
+        let tokens = |count: i64| {
+            (0..count)
+                .map(|number| Token::Mov(0, Value::Number(number + 1_000_000)))
+                .collect::<Vec<_>>()
+        };
+        let bytes = encode(&tokens(65536)).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), tokens(65536));
+        assert_eq!(encode(&tokens(65537)), Err(Error::TooManyImmediates));
+    }
+
+    #[test]
+    fn deduplicates_strings() {
+        let tokens = vec![
+            Token::Out("hi\n".to_owned()),
+            Token::Label("hi\n".to_owned()),
+            Token::Comment(String::new()),
+            Token::Out("ünïcode {0}".to_owned()),
+            Token::Jmp(Label::Base("hi\n".to_owned())),
+            Token::Out("hi\n".to_owned()),
+        ];
+        let bytes = encode(&tokens).unwrap();
+
+        // Every distinct string is stored once, prefixed by its length
+        let strings = 3 * 4 + "hi\n".len() + "ünïcode {0}".len();
+        assert_eq!(pool_size(&bytes, 2) as usize, strings);
+        assert_eq!(decode(&bytes).unwrap(), tokens);
+    }
+
+    #[test]
+    fn encodes_memory_addresses() {
+        let tokens = vec![
+            Token::Load(
+                3,
+                Address {
+                    base: None,
+                    label: None,
+                    offset: 1 << 40,
+                },
+            ),
+            Token::Store(
+                Address {
+                    base: Some(255),
+                    label: None,
+                    offset: -8,
+                },
+                Value::Number(70_000),
+            ),
+            Token::Store(
+                Address {
+                    base: Some(0),
+                    label: None,
+                    offset: 0,
+                },
+                Value::Register(7),
+            ),
+        ];
+        assert_eq!(decode(&encode(&tokens).unwrap()).unwrap(), tokens);
+    }
+
+    #[test]
+    fn rejects_unassembled_tokens() {
+        let label = Address {
+            base: None,
+            label: Some("table".to_owned()),
+            offset: 0,
+        };
+        assert_eq!(encode(&[Token::Load(0, label)]), Err(Error::Unassembled));
+        assert_eq!(
+            encode(&[Token::Directive(Directive::Data)]),
+            Err(Error::Unassembled)
+        );
+    }
+
+    #[test]
+    fn decodes_single_instructions() {
+        let tokens = vec![
+            Token::Mov(0, Value::Number(100_000)),
+            Token::Call(Label::Address(3)),
+            Token::Out("{0}\n".to_owned()),
+            Token::Ret,
+        ];
+        let bytes = encode(&tokens).unwrap();
+        let code = Code::new(&bytes).unwrap();
+        assert_eq!(code.len(), tokens.len());
+        for index in (0..tokens.len()).rev() {
+            assert_eq!(code.get(index).unwrap(), tokens[index]);
+        }
+        assert_eq!(code.get(tokens.len()), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn rejects_invalid_bytecode() {
+        let tokens = [
+            Token::Mov(0, Value::Number(100_000)),
+            Token::Out("text".to_owned()),
+        ];
+        let bytes = encode(&tokens).unwrap();
+
+        // Every prefix of the bytes is truncated
+        for length in 0..bytes.len() {
+            assert_eq!(decode(&bytes[..length]), Err(Error::Truncated), "{length}");
+        }
+
+        let mut unknown = bytes.clone();
+        unknown[HEADER_SIZE + INSTRUCTION_SIZE] = 200;
+        assert_eq!(
+            decode(&unknown),
+            Err(Error::UnknownOpcode {
+                index: 1,
+                opcode: 200
+            })
+        );
+
+        // Pool indices and string offsets outside the pools
+        let mut immediate = bytes.clone();
+        immediate[HEADER_SIZE + 4] = 1;
+        assert_eq!(decode(&immediate), Err(Error::InvalidOperand(0)));
+        let mut string = bytes;
+        string[HEADER_SIZE + INSTRUCTION_SIZE + 4] = 1;
+        assert_eq!(decode(&string), Err(Error::InvalidOperand(1)));
+    }
+}
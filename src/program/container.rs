@@ -0,0 +1,458 @@
+use std::fmt::{self, Display};
+
+/// The bytes every `.basmo` file starts with
+pub const MAGIC: [u8; 4] = *b"BASM";
+
+/// The version of the container format written by this build.
+/// Version 4 added the data section, which older builds would skip as an unknown section.
+pub const VERSION: u16 = 4;
+
+/// The flags this build understands, files with other flags set are rejected
+pub const KNOWN_FLAGS: u16 = 0;
+
+/// The size of the header: magic, version, flags, section count and checksum
+const HEADER_SIZE: usize = 14;
+
+/// The offset of the checksum in the header
+const CHECKSUM_OFFSET: usize = 10;
+
+/// The size of an entry in the section table: kind, offset and length
+const SECTION_ENTRY_SIZE: usize = 10;
+
+/// The kind of data stored in a section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    /// The tokens of the program, encoded with postcard
+    Code,
+
+    /// The tokens of the program, in the compact fixed-width encoding
+    CompactCode,
+
+    /// The names of removed labels and the source map, encoded with postcard
+    DebugInfo,
+
+    /// The initial content of memory, stored as is
+    Data,
+}
+
+impl SectionKind {
+    /// Returns the id of the section kind in the section table
+    #[must_use]
+    pub const fn id(self) -> u16 {
+        match self {
+            Self::Code => 1,
+            Self::CompactCode => 2,
+            Self::DebugInfo => 3,
+            Self::Data => 4,
+        }
+    }
+
+    /// Returns the name of the section kind
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Code => "code",
+            Self::CompactCode => "compact code",
+            Self::DebugInfo => "debug info",
+            Self::Data => "data",
+        }
+    }
+}
+
+/// An error that occurred while reading a container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerError {
+    /// The file doesn't start with the magic bytes
+    InvalidMagic,
+
+    /// The file was written in a different version of the format
+    UnsupportedVersion(u16),
+
+    /// The file uses features this build doesn't understand
+    UnsupportedFlags(u16),
+
+    /// The file ends before the header or a section does
+    Truncated,
+
+    /// The content of the file doesn't match the checksum in the header
+    ChecksumMismatch { expected: u32, found: u32 },
+
+    /// A section required to load the program is missing
+    MissingSection(SectionKind),
+}
+
+impl Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(
+                f,
+                "Not a basmo file, it may have been created by an older version. \
+                Compile the code again"
+            ),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Bytecode version {version} isn't supported, this build reads version {VERSION}. \
+                Compile the code again with this build"
+            ),
+            Self::UnsupportedFlags(flags) => {
+                write!(f, "Bytecode uses unsupported features (flags {flags:#06x})")
+            }
+            Self::Truncated => write!(f, "Bytecode file is truncated"),
+            Self::ChecksumMismatch { expected, found } => write!(
+                f,
+                "Bytecode file is corrupted, checksum {found:#010x} doesn't match {expected:#010x}"
+            ),
+            Self::MissingSection(kind) => {
+                write!(f, "Bytecode file doesn't contain a {} section", kind.name())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+/// The CRC-32 of every byte, to calculate checksums a byte at a time
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut byte: u32 = 0;
+    while byte < 256 {
+        let mut crc = byte;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[byte as usize] = crc;
+        byte += 1;
+    }
+    table
+};
+
+/// Calculates the CRC-32 checksum of the bytes
+fn crc32<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> u32 {
+    let crc = parts.into_iter().flatten().fold(u32::MAX, |crc, byte| {
+        CRC_TABLE[((crc ^ u32::from(*byte)) & 0xFF) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+/// Reads a little endian u16 at the offset
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, ContainerError> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or(ContainerError::Truncated)
+}
+
+/// Reads a little endian u32 at the offset
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ContainerError> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(ContainerError::Truncated)
+}
+
+/// A `.basmo` file: a header with magic bytes, format version, flags and checksum,
+/// followed by a table of sections and their content.
+///
+/// All numbers are little endian. The header is laid out as:
+/// magic (4 bytes), version (u16), flags (u16), section count (u16) and the CRC-32 checksum (u32)
+/// of every other byte in the file. Every entry in the section table contains the kind (u16),
+/// offset from the start of the file (u32) and length (u32) of a section.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Container {
+    flags: u16,
+    sections: Vec<(u16, Vec<u8>)>,
+}
+
+impl Container {
+    /// Creates an empty container
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the flags
+    #[must_use]
+    pub const fn flags(&self) -> u16 {
+        self.flags
+    }
+
+    /// Adds a section, replacing an existing section of the same kind
+    pub fn set_section(&mut self, kind: SectionKind, data: Vec<u8>) {
+        self.sections.retain(|(id, _)| *id != kind.id());
+        self.sections.push((kind.id(), data));
+    }
+
+    /// Returns the content of a section, if the container has it
+    #[must_use]
+    pub fn section(&self, kind: SectionKind) -> Option<&[u8]> {
+        self.sections
+            .iter()
+            .find(|(id, _)| *id == kind.id())
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Returns the content of a section
+    ///
+    /// # Errors
+    /// Returns an error if the container doesn't have the section.
+    pub fn required_section(&self, kind: SectionKind) -> Result<&[u8], ContainerError> {
+        self.section(kind)
+            .ok_or(ContainerError::MissingSection(kind))
+    }
+
+    /// Encodes the container
+    ///
+    /// # Panics
+    /// Panics if there are more than 65535 sections, or the file would be larger than 4 GiB.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let count = u16::try_from(self.sections.len()).expect("Too many sections");
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.flags.to_le_bytes());
+        bytes.extend_from_slice(&count.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+
+        // Write the section table, followed by the sections
+        let mut offset = HEADER_SIZE + SECTION_ENTRY_SIZE * self.sections.len();
+        for (id, data) in &self.sections {
+            let to_u32 = |value: usize| u32::try_from(value).expect("Bytecode file too large");
+            bytes.extend_from_slice(&id.to_le_bytes());
+            bytes.extend_from_slice(&to_u32(offset).to_le_bytes());
+            bytes.extend_from_slice(&to_u32(data.len()).to_le_bytes());
+            offset += data.len();
+        }
+        for (_, data) in &self.sections {
+            bytes.extend_from_slice(data);
+        }
+
+        // Store the checksum of everything but the checksum itself
+        let checksum = crc32([&bytes[..CHECKSUM_OFFSET], &bytes[HEADER_SIZE..]]);
+        bytes[CHECKSUM_OFFSET..HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a container, checking the header and checksum
+    ///
+    /// # Errors
+    /// Returns an error if the bytes aren't a container of the supported version,
+    /// use unknown flags, are truncated or don't match the checksum.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ContainerError> {
+        // Check the version first, as other versions may be laid out differently
+        if !bytes.starts_with(&MAGIC) {
+            return Err(ContainerError::InvalidMagic);
+        }
+        let version = read_u16(bytes, 4)?;
+        if version != VERSION {
+            return Err(ContainerError::UnsupportedVersion(version));
+        }
+
+        // Read the sections in the table, so truncated files are reported as such
+        let count = read_u16(bytes, 8)?;
+        let sections = (0..usize::from(count))
+            .map(|index| {
+                let entry = HEADER_SIZE + index * SECTION_ENTRY_SIZE;
+                let id = read_u16(bytes, entry)?;
+                let offset = read_u32(bytes, entry + 2)? as usize;
+                let length = read_u32(bytes, entry + 6)? as usize;
+                let data = offset
+                    .checked_add(length)
+                    .and_then(|end| bytes.get(offset..end))
+                    .ok_or(ContainerError::Truncated)?;
+                Ok((id, data.to_vec()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        // Check the integrity of the file, before trusting the flags
+        let expected = read_u32(bytes, CHECKSUM_OFFSET)?;
+        let found = crc32([&bytes[..CHECKSUM_OFFSET], &bytes[HEADER_SIZE..]]);
+        if expected != found {
+            return Err(ContainerError::ChecksumMismatch { expected, found });
+        }
+        let flags = read_u16(bytes, 6)?;
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(ContainerError::UnsupportedFlags(flags & !KNOWN_FLAGS));
+        }
+        Ok(Self { flags, sections })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        crc32, Container, ContainerError, SectionKind, CHECKSUM_OFFSET, HEADER_SIZE, VERSION,
+    };
+
+    /// Returns the bytes of a container with a code and a data section
+    fn container() -> Vec<u8> {
+        let mut container = Container::new();
+        container.set_section(SectionKind::Code, vec![1, 2, 3]);
+        container.set_section(SectionKind::Data, vec![4; 10]);
+        container.to_bytes()
+    }
+
+    /// Stores the checksum of the bytes, as if they were written that way
+    fn update_checksum(bytes: &mut [u8]) {
+        let checksum = crc32([&bytes[..CHECKSUM_OFFSET], &bytes[HEADER_SIZE..]]);
+        bytes[CHECKSUM_OFFSET..HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn calculates_crc32() {
+        assert_eq!(crc32([b"123456789".as_slice()]), 0xCBF4_3926);
+        assert_eq!(crc32([b"1234".as_slice(), b"56789"]), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn reads_written_sections() {
+        let mut written = Container::new();
+        written.set_section(SectionKind::Code, vec![1, 2, 3]);
+        written.set_section(SectionKind::Code, vec![5]);
+        written.set_section(SectionKind::DebugInfo, Vec::new());
+        let read = Container::from_bytes(&written.to_bytes()).unwrap();
+        assert_eq!(read, written);
+        assert_eq!(read.section(SectionKind::Code), Some([5].as_slice()));
+        assert_eq!(read.section(SectionKind::DebugInfo), Some([].as_slice()));
+        assert_eq!(
+            read.required_section(SectionKind::CompactCode),
+            Err(ContainerError::MissingSection(SectionKind::CompactCode))
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_sections() {
+        let mut bytes = container();
+        bytes[HEADER_SIZE..HEADER_SIZE + 2].copy_from_slice(&99u16.to_le_bytes());
+        update_checksum(&mut bytes);
+        let container = Container::from_bytes(&bytes).unwrap();
+        assert_eq!(container.section(SectionKind::Code), None);
+        assert_eq!(
+            container.section(SectionKind::Data),
+            Some([4; 10].as_slice())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_magic() {
+        let mut bytes = container();
+        bytes[0] = b'X';
+        assert_eq!(
+            Container::from_bytes(&bytes),
+            Err(ContainerError::InvalidMagic)
+        );
+        assert_eq!(
+            Container::from_bytes(&[]),
+            Err(ContainerError::InvalidMagic)
+        );
+
+        // Files from before the container format are postcard encoded tokens
+        assert_eq!(
+            Container::from_bytes(&[2, 0, 5]),
+            Err(ContainerError::InvalidMagic)
+        );
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = container();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Container::from_bytes(&bytes),
+            Err(ContainerError::UnsupportedVersion(VERSION + 1))
+        );
+
+        // The version is checked before the checksum, as the layout may differ
+        bytes.truncate(6);
+        assert_eq!(
+            Container::from_bytes(&bytes),
+            Err(ContainerError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_data_sections_in_other_versions() {
+        // The last version without a data section, which skipped it as unknown
+        const VERSION_WITHOUT_DATA: u16 = 3;
+
+        // Builds from before the data section only read their own version
+        let bytes = container();
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        assert_ne!(version, VERSION_WITHOUT_DATA);
+
+        // And files written by those builds are rejected by this one
+        let mut old = bytes;
+        old[4..6].copy_from_slice(&VERSION_WITHOUT_DATA.to_le_bytes());
+        update_checksum(&mut old);
+        assert_eq!(
+            Container::from_bytes(&old),
+            Err(ContainerError::UnsupportedVersion(VERSION_WITHOUT_DATA))
+        );
+    }
+
+    #[test]
+    fn rejects_checksum_mismatches() {
+        let bytes = container();
+        let expected = u32::from_le_bytes(bytes[CHECKSUM_OFFSET..HEADER_SIZE].try_into().unwrap());
+        for index in (0..bytes.len()).filter(|index| !(4..HEADER_SIZE).contains(index)) {
+            let mut corrupted = bytes.clone();
+            corrupted[index] ^= 0x40;
+            let result = Container::from_bytes(&corrupted);
+            if index < 4 {
+                assert_eq!(result, Err(ContainerError::InvalidMagic));
+                continue;
+            }
+
+            // Corrupting the section table may also point sections outside the file
+            match result {
+                Err(ContainerError::ChecksumMismatch {
+                    expected: stored,
+                    found,
+                }) => {
+                    assert_eq!(stored, expected);
+                    assert_ne!(found, expected);
+                }
+                result => assert_eq!(result, Err(ContainerError::Truncated), "byte {index}"),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = container();
+
+        // Cut in the header, the section table and the last section
+        for length in [8, 12, HEADER_SIZE + 5, HEADER_SIZE + 15, bytes.len() - 1] {
+            assert_eq!(
+                Container::from_bytes(&bytes[..length]),
+                Err(ContainerError::Truncated),
+                "length {length}"
+            );
+        }
+
+        // A section table with more sections than the file contains
+        let mut bytes = bytes;
+        bytes[8..10].copy_from_slice(&1000u16.to_le_bytes());
+        assert_eq!(
+            Container::from_bytes(&bytes),
+            Err(ContainerError::Truncated)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        let mut bytes = container();
+        bytes[6..8].copy_from_slice(&0x0102u16.to_le_bytes());
+        update_checksum(&mut bytes);
+        assert_eq!(
+            Container::from_bytes(&bytes),
+            Err(ContainerError::UnsupportedFlags(0x0102))
+        );
+    }
+}
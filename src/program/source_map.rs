@@ -0,0 +1,85 @@
+use crate::compile::span::Span;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// The location of an instruction in the code: the file, if it is known, line and column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub file: Option<&'a str>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Location<'_> {
+    /// Writes the location as `file:line:column`, or `line:column` if the file isn't known
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = self.file {
+            write!(f, "{file}:")?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Maps every token of a program back to the code it was compiled from.
+/// Stored in the debug info of `.basmo` files, so it is known after loading bytecode as well.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceMap {
+    file: Option<String>,
+    spans: Vec<Span>,
+}
+
+impl SourceMap {
+    /// Creates a source map from the location of every token in the code of the file
+    #[must_use]
+    pub const fn new(file: Option<String>, spans: Vec<Span>) -> Self {
+        Self { file, spans }
+    }
+
+    /// Returns the path of the file containing the code, if it is known
+    #[must_use]
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// Sets the path of the file containing the code
+    pub fn set_file(&mut self, file: Option<String>) {
+        self.file = file;
+    }
+
+    /// Returns the location in the code of the token at the index
+    #[must_use]
+    pub fn span(&self, index: usize) -> Option<Span> {
+        self.spans.get(index).copied()
+    }
+
+    /// Returns the file, line and column of the token at the index
+    #[must_use]
+    pub fn location(&self, index: usize) -> Option<Location<'_>> {
+        self.spans.get(index).map(|span| Location {
+            file: self.file(),
+            line: span.line,
+            column: span.column,
+        })
+    }
+
+    /// Returns the location in the code of every token
+    #[must_use]
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// Returns whether the location of no token is known
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Keeps only the locations of the tokens for which the predicate returns true
+    pub fn retain(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        let mut index = 0;
+        self.spans.retain(|_| {
+            index += 1;
+            keep(index - 1)
+        });
+    }
+}
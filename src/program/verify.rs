@@ -0,0 +1,265 @@
+use crate::{compile::span::Span, execute::WORD_SIZE, token::Label, value::Address, Token};
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+};
+
+/// The kind of problem found by the verifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyErrorKind {
+    /// A branching instruction jumps to an address after the end of the program
+    JumpOutOfRange(usize),
+
+    /// A branching instruction jumps to a label that doesn't exist
+    UndefinedLabel(String),
+
+    /// An out instruction prints a register that doesn't exist, like `{256}`
+    InvalidOutputRegister(String),
+
+    /// A memory address still uses a label, which should have been replaced by its address
+    UnresolvedAddress(String),
+
+    /// A memory address without a register reads or writes a word outside the data section
+    AddressOutOfData(i64),
+}
+
+impl Display for VerifyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::JumpOutOfRange(address) => {
+                write!(f, "Jump to address {address}, after the end of the program")
+            }
+            Self::UndefinedLabel(label) => write!(f, "Label \"{label}\" doesn't exist"),
+            Self::InvalidOutputRegister(register) => {
+                write!(f, "Output references invalid register \"{{{register}}}\"")
+            }
+            Self::UnresolvedAddress(label) => {
+                write!(
+                    f,
+                    "Memory address uses label \"{label}\", which wasn't replaced"
+                )
+            }
+            Self::AddressOutOfData(address) => {
+                write!(f, "Memory address {address} is outside the data section")
+            }
+        }
+    }
+}
+
+/// A problem found in a program before running it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    kind: VerifyErrorKind,
+    index: usize,
+    location: Option<Span>,
+}
+
+impl VerifyError {
+    /// Returns the kind of problem
+    #[must_use]
+    pub const fn kind(&self) -> &VerifyErrorKind {
+        &self.kind
+    }
+
+    /// Returns the index of the instruction with the problem
+    #[must_use]
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the location in the code of the instruction, if it is known
+    #[must_use]
+    pub const fn location(&self) -> Option<Span> {
+        self.location
+    }
+
+    /// Sets the location in the code of the instruction
+    #[must_use]
+    pub const fn with_location(mut self, location: Option<Span>) -> Self {
+        self.location = location;
+        self
+    }
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at instruction {}", self.kind, self.index)?;
+        if let Some(location) = self.location {
+            write!(f, " ({}:{})", location.line, location.column)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Returns the register references in the output that aren't valid registers.
+/// Reads the output the same way the out instruction does.
+fn invalid_output_registers(output: &str) -> Vec<String> {
+    let mut invalid = Vec::new();
+    let mut chars = output.chars();
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            continue;
+        }
+
+        // Read until the closing '}', another '{' means the '{' is printed
+        let mut register = String::new();
+        let mut closed = false;
+        for c in chars.by_ref() {
+            if c == '{' {
+                break;
+            }
+            if c == '}' {
+                closed = true;
+                break;
+            }
+            register.push(c);
+        }
+        if closed && register.parse::<u8>().is_err() {
+            invalid.push(register);
+        }
+    }
+    invalid
+}
+
+/// Returns the problem with a memory address, if it uses a label or is a constant address
+/// of a word that isn't inside the data section
+fn invalid_address(address: &Address, data_size: usize) -> Option<VerifyErrorKind> {
+    if let Some(label) = &address.label {
+        return Some(VerifyErrorKind::UnresolvedAddress(label.clone()));
+    }
+    let in_data = usize::try_from(address.offset)
+        .ok()
+        .and_then(|start| start.checked_add(WORD_SIZE))
+        .is_some_and(|end| end <= data_size);
+    (address.base.is_none() && !in_data)
+        .then_some(VerifyErrorKind::AddressOutOfData(address.offset))
+}
+
+/// Checks the tokens can be executed with a data section of `data_size` bytes.
+///
+/// Every jump target has to be inside the program, every label jumped to has to exist,
+/// out instructions may only print existing registers and memory addresses without
+/// a register have to be inside the data section.
+///
+/// # Errors
+/// Returns every problem found, in the order of the instructions.
+pub fn verify(tokens: &[Token], data_size: usize) -> Result<(), Vec<VerifyError>> {
+    let labels = tokens
+        .iter()
+        .filter_map(|token| match token {
+            Token::Label(label) => Some(label.as_str()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let mut errors = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        let mut error = |kind| {
+            errors.push(VerifyError {
+                kind,
+                index,
+                location: None,
+            });
+        };
+        match token {
+            Token::Jmp(label)
+            | Token::Jl(label)
+            | Token::Jg(label)
+            | Token::Je(label)
+            | Token::Call(label) => match label {
+                Label::Address(address) if *address > tokens.len() => {
+                    error(VerifyErrorKind::JumpOutOfRange(*address));
+                }
+                Label::Base(name) if !labels.contains(name.as_str()) => {
+                    error(VerifyErrorKind::UndefinedLabel(name.clone()));
+                }
+                Label::Address(_) | Label::Base(_) => {}
+            },
+            Token::Out(output) => invalid_output_registers(output)
+                .into_iter()
+                .for_each(|register| error(VerifyErrorKind::InvalidOutputRegister(register))),
+            Token::Load(_, address) | Token::Store(address, _) => {
+                if let Some(kind) = invalid_address(address, data_size) {
+                    error(kind);
+                }
+            }
+            _ => {}
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify, VerifyErrorKind};
+    use crate::{
+        value::{Address, Value},
+        Token,
+    };
+
+    /// Returns the kinds of the problems found in the tokens
+    fn problems(tokens: &[Token], data_size: usize) -> Vec<VerifyErrorKind> {
+        verify(tokens, data_size).map_or_else(
+            |errors| errors.iter().map(|error| error.kind().clone()).collect(),
+            |()| Vec::new(),
+        )
+    }
+
+    const fn address(base: Option<u8>, offset: i64) -> Address {
+        Address {
+            base,
+            label: None,
+            offset,
+        }
+    }
+
+    #[test]
+    fn accepts_addresses_in_the_data_section() {
+        let tokens = [
+            Token::Load(0, address(None, 0)),
+            Token::Store(address(None, 8), Value::Number(1)),
+            Token::Load(0, address(Some(1), 1 << 20)),
+            Token::Store(address(Some(1), -8), Value::Register(0)),
+        ];
+        assert_eq!(problems(&tokens, 16), []);
+    }
+
+    #[test]
+    fn rejects_addresses_outside_the_data_section() {
+        let tokens = [
+            Token::Load(0, address(None, 9)),
+            Token::Store(address(None, -1), Value::Number(1)),
+            Token::Load(0, address(None, i64::MAX)),
+        ];
+        assert_eq!(
+            problems(&tokens, 16),
+            [
+                VerifyErrorKind::AddressOutOfData(9),
+                VerifyErrorKind::AddressOutOfData(-1),
+                VerifyErrorKind::AddressOutOfData(i64::MAX),
+            ]
+        );
+        assert_eq!(
+            problems(&[Token::Load(0, address(None, 0))], 0),
+            [VerifyErrorKind::AddressOutOfData(0)]
+        );
+    }
+
+    #[test]
+    fn rejects_addresses_with_labels() {
+        let address = Address {
+            base: Some(1),
+            label: Some("table".to_owned()),
+            offset: 0,
+        };
+        assert_eq!(
+            problems(&[Token::Store(address, Value::Number(0))], 64),
+            [VerifyErrorKind::UnresolvedAddress("table".to_owned())]
+        );
+    }
+}
@@ -1,14 +1,17 @@
 use crate::{
     compile::{code::Code, error::Error},
-    value::Value,
+    value::{Address, Value},
 };
-pub mod argument;
+pub(crate) mod argument;
+pub mod directive;
 
 use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Write as _};
 
-use self::argument::read_reg_args;
+use self::argument::{read_address_value, read_arguments, read_reg_address, read_reg_args};
+use self::directive::Directive;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Token {
     Comment(String),
     Out(String),
@@ -24,17 +27,109 @@ pub enum Token {
     Jg(Label),
     Je(Label),
     Cmp(Value, Value),
+    And(u8, Value, Value),
+    Or(u8, Value, Value),
+    Xor(u8, Value, Value),
+    Not(u8, Value),
+    Shl(u8, Value, Value),
+    Shr(u8, Value, Value),
+    Sar(u8, Value, Value),
+    Rol(u8, Value, Value),
+    Ror(u8, Value, Value),
+    Call(Label),
+    Ret,
+    Push(Value),
+    Pop(u8),
+    Peek(u8, Value),
+    Load(u8, Address),
+    Store(Address, Value),
+    Directive(Directive),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Label {
     Base(String),
     Address(usize),
 }
 
+impl Display for Label {
+    /// Writes the name of the label.
+    /// Addresses are written as number, which can't be read back as label.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Base(name) => write!(f, "{name}"),
+            Self::Address(address) => write!(f, "{address}"),
+        }
+    }
+}
+
+/// Writes the text as a string literal, escaping characters where needed
+pub(crate) fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in text.chars() {
+        match c {
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\0' => f.write_str("\\0")?,
+            '\\' | '"' => write!(f, "\\{c}")?,
+            _ => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl Display for Token {
+    /// Writes the token the way it is written in the code
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Comment(comment) => write!(f, "/*{comment}*/"),
+            Self::Out(output) => {
+                f.write_str("out ")?;
+                write_string(f, output)
+            }
+            Self::Mov(register, value) => write!(f, "mov r{register}, {value}"),
+            Self::Add(register, left, right) => write!(f, "add r{register}, {left}, {right}"),
+            Self::Sub(register, left, right) => write!(f, "sub r{register}, {left}, {right}"),
+            Self::Mul(register, left, right) => write!(f, "mul r{register}, {left}, {right}"),
+            Self::Div(register, left, right) => write!(f, "div r{register}, {left}, {right}"),
+            Self::Mod(register, left, right) => write!(f, "mod r{register}, {left}, {right}"),
+            Self::Label(name) => write!(f, "{name}:"),
+            Self::Jmp(label) => write!(f, "jmp {label}"),
+            Self::Jl(label) => write!(f, "jl {label}"),
+            Self::Jg(label) => write!(f, "jg {label}"),
+            Self::Je(label) => write!(f, "je {label}"),
+            Self::Cmp(left, right) => write!(f, "cmp {left}, {right}"),
+            Self::And(register, left, right) => write!(f, "and r{register}, {left}, {right}"),
+            Self::Or(register, left, right) => write!(f, "or r{register}, {left}, {right}"),
+            Self::Xor(register, left, right) => write!(f, "xor r{register}, {left}, {right}"),
+            Self::Not(register, value) => write!(f, "not r{register}, {value}"),
+            Self::Shl(register, left, right) => write!(f, "shl r{register}, {left}, {right}"),
+            Self::Shr(register, left, right) => write!(f, "shr r{register}, {left}, {right}"),
+            Self::Sar(register, left, right) => write!(f, "sar r{register}, {left}, {right}"),
+            Self::Rol(register, left, right) => write!(f, "rol r{register}, {left}, {right}"),
+            Self::Ror(register, left, right) => write!(f, "ror r{register}, {left}, {right}"),
+            Self::Call(label) => write!(f, "call {label}"),
+            Self::Ret => write!(f, "ret"),
+            Self::Push(value) => write!(f, "push {value}"),
+            Self::Pop(register) => write!(f, "pop r{register}"),
+            Self::Peek(register, offset) => write!(f, "peek r{register}, {offset}"),
+            Self::Load(register, address) => write!(f, "load r{register}, {address}"),
+            Self::Store(address, value) => write!(f, "store {address}, {value}"),
+            Self::Directive(directive) => write!(f, "{directive}"),
+        }
+    }
+}
+
 impl Token {
+    /// Returns whether the token is only used while compiling, and doesn't do anything when executed
+    #[must_use]
+    pub const fn is_no_op(&self) -> bool {
+        matches!(self, Self::Comment(_) | Self::Label(_))
+    }
+
     /// Reads the arguments of the move operation and returns the operation with arguments
-    pub fn mov(code: &mut Code) -> Result<Self, Error> {
+    pub(crate) fn mov(code: &mut Code) -> Result<Self, Error> {
         // Read the arguments
         let (register, arguments) = read_reg_args::<1>(code)?;
 
@@ -43,7 +138,7 @@ impl Token {
     }
 
     /// Reads the add operation, returns the add operation with arguments
-    pub fn add(code: &mut Code) -> Result<Self, Error> {
+    pub(crate) fn add(code: &mut Code) -> Result<Self, Error> {
         // Read the arguments
         let (register, arguments) = read_reg_args::<2>(code)?;
 
@@ -52,7 +147,7 @@ impl Token {
     }
 
     /// Reads the sub operation, returns the sub operation with arguments
-    pub fn sub(code: &mut Code) -> Result<Self, Error> {
+    pub(crate) fn sub(code: &mut Code) -> Result<Self, Error> {
         // Read the arguments
         let (register, arguments) = read_reg_args::<2>(code)?;
 
@@ -61,7 +156,7 @@ impl Token {
     }
 
     /// Reads the mul operation, returns the mul operation with arguments
-    pub fn mul(code: &mut Code) -> Result<Self, Error> {
+    pub(crate) fn mul(code: &mut Code) -> Result<Self, Error> {
         // Read the arguments
         let (register, arguments) = read_reg_args::<2>(code)?;
 
@@ -70,7 +165,7 @@ impl Token {
     }
 
     /// Reads the div operation, returns the div operation with arguments
-    pub fn div(code: &mut Code) -> Result<Self, Error> {
+    pub(crate) fn div(code: &mut Code) -> Result<Self, Error> {
         // Read the arguments
         let (register, arguments) = read_reg_args::<2>(code)?;
 
@@ -79,11 +174,137 @@ impl Token {
     }
 
     /// Reads the mod operation, returns the mod operation with arguments
-    pub fn modulo(code: &mut Code) -> Result<Self, Error> {
+    pub(crate) fn modulo(code: &mut Code) -> Result<Self, Error> {
         // Read the arguments
         let (register, arguments) = read_reg_args::<2>(code)?;
 
         // Return the mod operation
         Ok(Self::Mod(register, arguments[0], arguments[1]))
     }
+
+    /// Reads the and operation, returns the and operation with arguments
+    pub(crate) fn and(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, arguments) = read_reg_args::<2>(code)?;
+
+        // Return the and operation
+        Ok(Self::And(register, arguments[0], arguments[1]))
+    }
+
+    /// Reads the or operation, returns the or operation with arguments
+    pub(crate) fn or(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, arguments) = read_reg_args::<2>(code)?;
+
+        // Return the or operation
+        Ok(Self::Or(register, arguments[0], arguments[1]))
+    }
+
+    /// Reads the xor operation, returns the xor operation with arguments
+    pub(crate) fn xor(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, arguments) = read_reg_args::<2>(code)?;
+
+        // Return the xor operation
+        Ok(Self::Xor(register, arguments[0], arguments[1]))
+    }
+
+    /// Reads the not operation, returns the not operation with arguments
+    pub(crate) fn not(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, arguments) = read_reg_args::<1>(code)?;
+
+        // Return the not operation
+        Ok(Self::Not(register, arguments[0]))
+    }
+
+    /// Reads the shl operation, returns the shl operation with arguments
+    pub(crate) fn shl(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, arguments) = read_reg_args::<2>(code)?;
+
+        // Return the shl operation
+        Ok(Self::Shl(register, arguments[0], arguments[1]))
+    }
+
+    /// Reads the shr operation, returns the shr operation with arguments
+    pub(crate) fn shr(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, arguments) = read_reg_args::<2>(code)?;
+
+        // Return the shr operation
+        Ok(Self::Shr(register, arguments[0], arguments[1]))
+    }
+
+    /// Reads the sar operation, returns the sar operation with arguments
+    pub(crate) fn sar(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, arguments) = read_reg_args::<2>(code)?;
+
+        // Return the sar operation
+        Ok(Self::Sar(register, arguments[0], arguments[1]))
+    }
+
+    /// Reads the rol operation, returns the rol operation with arguments
+    pub(crate) fn rol(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, arguments) = read_reg_args::<2>(code)?;
+
+        // Return the rol operation
+        Ok(Self::Rol(register, arguments[0], arguments[1]))
+    }
+
+    /// Reads the ror operation, returns the ror operation with arguments
+    pub(crate) fn ror(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, arguments) = read_reg_args::<2>(code)?;
+
+        // Return the ror operation
+        Ok(Self::Ror(register, arguments[0], arguments[1]))
+    }
+
+    /// Reads the push operation, returns the push operation with its argument
+    pub(crate) fn push(code: &mut Code) -> Result<Self, Error> {
+        // Read the argument
+        let arguments = read_arguments::<1>(code)?;
+
+        // Return the push operation
+        Ok(Self::Push(arguments[0]))
+    }
+
+    /// Reads the pop operation, returns the pop operation with its register
+    pub(crate) fn pop(code: &mut Code) -> Result<Self, Error> {
+        // Read the register
+        let (register, []) = read_reg_args::<0>(code)?;
+
+        // Return the pop operation
+        Ok(Self::Pop(register))
+    }
+
+    /// Reads the peek operation, returns the peek operation with arguments
+    pub(crate) fn peek(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, arguments) = read_reg_args::<1>(code)?;
+
+        // Return the peek operation
+        Ok(Self::Peek(register, arguments[0]))
+    }
+
+    /// Reads the load operation, returns the load operation with its register and address
+    pub(crate) fn load(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (register, address) = read_reg_address(code)?;
+
+        // Return the load operation
+        Ok(Self::Load(register, address))
+    }
+
+    /// Reads the store operation, returns the store operation with its address and value
+    pub(crate) fn store(code: &mut Code) -> Result<Self, Error> {
+        // Read the arguments
+        let (address, value) = read_address_value(code)?;
+
+        // Return the store operation
+        Ok(Self::Store(address, value))
+    }
 }
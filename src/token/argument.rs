@@ -1,6 +1,10 @@
 use crate::{
-    compile::{code::Code, error::Error},
-    value::Value,
+    compile::{
+        code::Code,
+        error::{Error, ErrorKind},
+        span::Span,
+    },
+    value::{Address, Value},
 };
 
 /// Skips all whitespace, but errors on new lines
@@ -8,7 +12,7 @@ pub fn skip_whitespace(code: &mut Code) -> Result<Option<char>, Error> {
     let mut last_char = code.next();
     while last_char.is_some_and(char::is_whitespace) {
         if last_char == Some('\n') {
-            return Err(Error::end_of_line(code.line(), code.column()));
+            return Err(Error::end_of_line(code.span()));
         }
         last_char = code.next();
     }
@@ -16,62 +20,66 @@ pub fn skip_whitespace(code: &mut Code) -> Result<Option<char>, Error> {
 }
 
 /// Reads until the first non-whitespace character.
-/// Returns the last read character, which is either non-whitespace or the last char of the code.
-fn read_until_whitespace(code: &mut Code, mut last_char: char) -> (String, char) {
+/// Returns the read argument with its span,
+/// and the last read character, which is either non-whitespace or the last char of the code.
+fn read_until_whitespace(code: &mut Code, mut last_char: char) -> (String, Span, char) {
     let mut argument = last_char.to_string();
-    for c in code.by_ref() {
+    let mut span = code.span();
+    while let Some(c) = code.next() {
         if c == ',' || c.is_whitespace() {
             last_char = c;
             break;
         }
         argument.push(c);
+        span = span.to(code.span());
         last_char = c;
     }
-    (argument, last_char)
+    (argument, span, last_char)
 }
 
 /// Reads the first argument of most operations
-fn read_first_argument(code: &mut Code) -> Result<(Value, char), Error> {
+fn read_first_argument(code: &mut Code) -> Result<(Value, Span, char), Error> {
     // Skip the whitespace
     let Some(c) = skip_whitespace(code)? else {
-        return Err(Error::end_of_file(code.line(), code.column()));
+        return Err(Error::end_of_file(code.span()));
     };
 
     // Read the argument
-    let (argument, last_char) = read_until_whitespace(code, c);
+    let (argument, span, last_char) = read_until_whitespace(code, c);
 
-    // Convert the argument to a value and return it with the last read char
-    Ok((Value::from_str(&argument, code), last_char))
+    // Convert the argument to a value and return it with its span and the last read char
+    Ok((Value::from_str(&argument, span)?, span, last_char))
 }
 
-/// Reads later arguments
-fn read_later_argument(code: &mut Code, c: char) -> Result<(Value, char), Error> {
+/// Skips the seperator before a later argument.
+/// Returns the first character of the argument.
+fn read_seperator(code: &mut Code, c: char) -> Result<char, Error> {
     // Look for the first seperator
     let mut seperator_found = c == ',';
     while !seperator_found {
         match code.next() {
-            None => return Err(Error::end_of_file(code.line(), code.column())),
+            None => return Err(Error::end_of_file(code.span())),
             Some(',') => seperator_found = true,
-            Some('\n') => return Err(Error::end_of_line(code.line(), code.column())),
+            Some('\n') => return Err(Error::end_of_line(code.span())),
             Some(c) if c.is_whitespace() => {}
-            Some(c) => panic!(
-                "Unexpected character \"{c}\": {}:{}",
-                code.line(),
-                code.column()
-            ),
+            Some(c) => return Err(Error::new(ErrorKind::UnexpectedCharacter(c), code.span())),
         }
     }
 
     // Skip all whitespace
-    let Some(last_char) = skip_whitespace(code)? else {
-        return Err(Error::end_of_file(code.line(), code.column()));
-    };
+    skip_whitespace(code)?.ok_or_else(|| Error::end_of_file(code.span()))
+}
+
+/// Reads later arguments
+fn read_later_argument(code: &mut Code, c: char) -> Result<(Value, char), Error> {
+    // Skip the seperator
+    let last_char = read_seperator(code, c)?;
 
     // Read until whitespace or a seperator is found
-    let (value, last_char) = read_until_whitespace(code, last_char);
+    let (value, span, last_char) = read_until_whitespace(code, last_char);
 
     // Convert the string to a value and return it with the last read char
-    Ok((Value::from_str(&value, code), last_char))
+    Ok((Value::from_str(&value, span)?, last_char))
 }
 
 /// Reads one register and multiple arguments.
@@ -82,7 +90,7 @@ pub fn read_arguments<const SIZE: usize>(code: &mut Code) -> Result<[Value; SIZE
     assert!(SIZE > 0);
 
     // Read the first argument
-    let (value, mut ch) = read_first_argument(code)?;
+    let (value, _, mut ch) = read_first_argument(code)?;
     arguments[0] = value;
 
     // Read arguments
@@ -97,16 +105,10 @@ pub fn read_arguments<const SIZE: usize>(code: &mut Code) -> Result<[Value; SIZE
 pub fn read_reg_args<const SIZE: usize>(code: &mut Code) -> Result<(u8, [Value; SIZE]), Error> {
     // Initialize the arguments array and read the register
     let mut arguments = [Value::Number(0); SIZE];
-    let (value, mut ch) = read_first_argument(code)?;
+    let (value, span, mut ch) = read_first_argument(code)?;
 
     // Make sure the first argument is a register
-    let Value::Register(register) = value else {
-        panic!(
-            "You can only move into registers: {}:{}",
-            code.line(),
-            code.column()
-        );
-    };
+    let register = value.register(span)?;
 
     // Read arguments
     for arg in &mut arguments {
@@ -116,3 +118,88 @@ pub fn read_reg_args<const SIZE: usize>(code: &mut Code) -> Result<(u8, [Value;
     }
     Ok((register, arguments))
 }
+
+/// Reads a memory address between brackets, starting at the passed char.
+/// Returns the address and the closing bracket.
+fn read_address(code: &mut Code, first: char) -> Result<(Address, char), Error> {
+    // Error on arguments that don't start with a bracket
+    if first != '[' {
+        let (argument, span, _) = read_until_whitespace(code, first);
+        return Err(Error::new(ErrorKind::InvalidAddress(argument), span));
+    }
+
+    // Read until the closing bracket, which has to be on the same line
+    let mut address = first.to_string();
+    let mut span = code.span();
+    while !address.ends_with(']') {
+        match code.next() {
+            None => return Err(Error::end_of_file(code.span())),
+            Some('\n') => return Err(Error::end_of_line(code.span())),
+            Some(c) => {
+                address.push(c);
+                span = span.to(code.span());
+            }
+        }
+    }
+    Ok((Address::from_str(&address, span)?, ']'))
+}
+
+/// Reads a register followed by a memory address
+pub fn read_reg_address(code: &mut Code) -> Result<(u8, Address), Error> {
+    // Read the register
+    let (value, span, ch) = read_first_argument(code)?;
+    let register = value.register(span)?;
+
+    // Read the address
+    let first = read_seperator(code, ch)?;
+    let (address, _) = read_address(code, first)?;
+    Ok((register, address))
+}
+
+/// Reads a memory address followed by a value
+pub fn read_address_value(code: &mut Code) -> Result<(Address, Value), Error> {
+    // Read the address
+    let Some(first) = skip_whitespace(code)? else {
+        return Err(Error::end_of_file(code.span()));
+    };
+    let (address, ch) = read_address(code, first)?;
+
+    // Read the value
+    let (value, _) = read_later_argument(code, ch)?;
+    Ok((address, value))
+}
+
+/// Reads a list of arguments separated by ',', which ends at the end of the line.
+/// Returns every argument with its span.
+pub fn read_list(code: &mut Code) -> Result<Vec<(String, Span)>, Error> {
+    // Read the first argument
+    let Some(c) = skip_whitespace(code)? else {
+        return Err(Error::end_of_file(code.span()));
+    };
+    let (argument, span, mut last_char) = read_until_whitespace(code, c);
+    let mut arguments = vec![(argument, span)];
+
+    loop {
+        // Skip the whitespace after the argument, without moving to the next line
+        if last_char != ',' {
+            if code.eof() || last_char == '\n' {
+                break;
+            }
+            while code.peek().is_some_and(|c| c.is_whitespace() && c != '\n') {
+                code.next();
+            }
+
+            // The list ends, unless a seperator follows
+            if code.peek() != Some(',') {
+                break;
+            }
+        }
+
+        // Read the next argument
+        let first = read_seperator(code, last_char)?;
+        let (argument, span, c) = read_until_whitespace(code, first);
+        arguments.push((argument, span));
+        last_char = c;
+    }
+    Ok(arguments)
+}
@@ -0,0 +1,221 @@
+use super::{
+    argument::{read_list, skip_whitespace},
+    write_string,
+};
+use crate::{
+    compile::{
+        code::Code,
+        error::{Error, ErrorKind},
+        span::Span,
+    },
+    execute::{MAX_MEMORY_SIZE, WORD_SIZE},
+};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// A directive of the assembler, which lays out the data section instead of being executed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Directive {
+    /// `.data`, the following lines are the data section
+    Data,
+
+    /// `.text`, the following lines are instructions again
+    Text,
+
+    /// `.word 1, 2`, 64-bit little endian words
+    Word(Vec<i64>),
+
+    /// `.byte 1, 2`, single bytes
+    Byte(Vec<u8>),
+
+    /// `.ascii "text"`, the UTF-8 bytes of the text
+    Ascii(String),
+
+    /// `.zero 64`, a number of zero bytes
+    Zero(usize),
+}
+
+impl Display for Directive {
+    /// Writes the directive the way it is written in the code
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_list =
+            |f: &mut fmt::Formatter<'_>, values: Vec<String>| f.write_str(&values.join(", "));
+        match self {
+            Self::Data => write!(f, ".data"),
+            Self::Text => write!(f, ".text"),
+            Self::Word(words) => {
+                f.write_str(".word ")?;
+                write_list(f, words.iter().map(ToString::to_string).collect())
+            }
+            Self::Byte(bytes) => {
+                f.write_str(".byte ")?;
+                write_list(f, bytes.iter().map(ToString::to_string).collect())
+            }
+            Self::Ascii(text) => {
+                f.write_str(".ascii ")?;
+                write_string(f, text)
+            }
+            Self::Zero(count) => write!(f, ".zero {count}"),
+        }
+    }
+}
+
+/// Parses a number argument of a directive
+fn parse_number<T: std::str::FromStr>((argument, span): (String, Span)) -> Result<T, Error> {
+    argument
+        .parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidData(argument), span))
+}
+
+impl Directive {
+    /// Returns the name of the directive, like `.word`
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Data => ".data",
+            Self::Text => ".text",
+            Self::Word(_) => ".word",
+            Self::Byte(_) => ".byte",
+            Self::Ascii(_) => ".ascii",
+            Self::Zero(_) => ".zero",
+        }
+    }
+
+    /// Returns whether the directive adds data, rather than switching sections
+    #[must_use]
+    pub const fn is_data(&self) -> bool {
+        !matches!(self, Self::Data | Self::Text)
+    }
+
+    /// Returns the number of bytes the directive lays out
+    #[must_use]
+    pub const fn size(&self) -> usize {
+        match self {
+            Self::Data | Self::Text => 0,
+            Self::Word(words) => words.len().saturating_mul(WORD_SIZE),
+            Self::Byte(bytes) => bytes.len(),
+            Self::Ascii(text) => text.len(),
+            Self::Zero(count) => *count,
+        }
+    }
+
+    /// Appends the bytes the directive lays out to the data.
+    /// The data doesn't grow past [`MAX_MEMORY_SIZE`], the bytes that don't fit are left out.
+    pub fn write_data(&self, data: &mut Vec<u8>) {
+        let room = MAX_MEMORY_SIZE.saturating_sub(data.len());
+        match self {
+            Self::Data | Self::Text => {}
+            Self::Word(words) => {
+                data.extend(words.iter().flat_map(|word| word.to_le_bytes()).take(room));
+            }
+            Self::Byte(bytes) => data.extend_from_slice(&bytes[..bytes.len().min(room)]),
+            Self::Ascii(text) => data.extend_from_slice(&text.as_bytes()[..text.len().min(room)]),
+            Self::Zero(count) => data.resize(data.len() + room.min(*count), 0),
+        }
+    }
+
+    /// Reads the arguments of the directive with the passed name, if it exists
+    pub(crate) fn read(name: &str, code: &mut Code) -> Result<Option<Self>, Error> {
+        Ok(Some(match name {
+            ".data" => Self::Data,
+            ".text" => Self::Text,
+            ".word" => Self::Word(
+                read_list(code)?
+                    .into_iter()
+                    .map(parse_number)
+                    .collect::<Result<_, _>>()?,
+            ),
+            ".byte" => Self::Byte(
+                read_list(code)?
+                    .into_iter()
+                    .map(parse_number)
+                    .collect::<Result<_, _>>()?,
+            ),
+            ".ascii" => {
+                // Only strings between double quotes are allowed
+                let Some(c) = skip_whitespace(code)? else {
+                    return Err(Error::end_of_file(code.span()));
+                };
+                if c != '"' {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData(c.to_string()),
+                        code.span(),
+                    ));
+                }
+                Self::Ascii(crate::compile::read_string(code)?)
+            }
+            ".zero" => {
+                let mut arguments = read_list(code)?.into_iter();
+                let count = parse_number(arguments.next().unwrap_or_default())?;
+                if let Some((argument, span)) = arguments.next() {
+                    return Err(Error::new(ErrorKind::InvalidData(argument), span));
+                }
+                Self::Zero(count)
+            }
+            _ => return Ok(None),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Directive;
+    use crate::{
+        compile::{error::ErrorKind, split_spanned_tokens},
+        execute::MAX_MEMORY_SIZE,
+    };
+
+    #[test]
+    fn lays_out_data() {
+        let mut data = Vec::new();
+        Directive::Word(vec![1, -1]).write_data(&mut data);
+        Directive::Byte(vec![2]).write_data(&mut data);
+        Directive::Ascii("hi".to_owned()).write_data(&mut data);
+        Directive::Zero(2).write_data(&mut data);
+        Directive::Text.write_data(&mut data);
+        let mut expected = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        expected.extend([255; 8]);
+        expected.extend([2, b'h', b'i', 0, 0]);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn does_not_grow_past_the_memory() {
+        let mut data = vec![0; MAX_MEMORY_SIZE - 4];
+        Directive::Word(vec![-1]).write_data(&mut data);
+        Directive::Zero(usize::MAX).write_data(&mut data);
+        assert_eq!(data.len(), MAX_MEMORY_SIZE);
+        assert_eq!(data[MAX_MEMORY_SIZE - 4..], [255; 4]);
+    }
+
+    #[test]
+    fn rejects_data_larger_than_the_memory() {
+        let invalid_data = |code: &str| {
+            let errors = split_spanned_tokens(code).unwrap_err();
+            assert_eq!(errors.len(), 1, "{errors:?}");
+            let ErrorKind::InvalidData(data) = errors[0].kind() else {
+                panic!("{:?}", errors[0]);
+            };
+            (data.clone(), errors[0].line())
+        };
+        assert_eq!(
+            invalid_data(".data\n.zero 99999999999999\n.zero 1\n"),
+            ("99999999999999".to_owned(), 2)
+        );
+        assert_eq!(
+            invalid_data(&format!(
+                ".data\n.zero {}\n.byte 1, 2\n",
+                MAX_MEMORY_SIZE - 1
+            )),
+            (".byte".to_owned(), 3)
+        );
+        assert_eq!(
+            invalid_data(&format!(
+                ".data\n.zero {MAX_MEMORY_SIZE}\n.zero {}\n",
+                usize::MAX
+            )),
+            (usize::MAX.to_string(), 3)
+        );
+        assert!(split_spanned_tokens(&format!(".data\n.zero {MAX_MEMORY_SIZE}\n")).is_ok());
+    }
+}
@@ -1,7 +1,14 @@
-use crate::compile::code::Code;
+use crate::compile::{
+    error::{Error, ErrorKind},
+    span::Span,
+};
+use std::fmt::Write as _;
+
+/// The register holding the stack pointer, which can also be written as `sp`
+pub const STACK_POINTER: u8 = 255;
 
 /// A value that can be taken as argument by a token
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Value {
     Number(i64),
     Register(u8),
@@ -11,13 +18,18 @@ impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Number(value) => write!(f, "{value}"),
-            Self::Register(value) => write!(f, "{value}"),
+            Self::Register(value) => write!(f, "r{value}"),
         }
     }
 }
 
 impl Value {
-    pub fn from_str(value: &str, code: &Code) -> Self {
+    pub(crate) fn from_str(value: &str, span: Span) -> Result<Self, Error> {
+        // sp is the name of the stack pointer register
+        if value == "sp" {
+            return Ok(Self::Register(STACK_POINTER));
+        }
+
         // If the value starts with 'r', it's a register
         if value.starts_with('r') {
             // Skip the r and trim the rest
@@ -25,33 +37,46 @@ impl Value {
             let register = register.trim();
 
             // Try to parse it to a u8 as there are 256 registers
-            // Panic on failure
+            // Error on failure
             register.parse::<u8>().map_or_else(
                 |_| {
-                    panic!(
-                        "Invalid register id \"{register}\": {}:{}",
-                        code.line(),
-                        code.column()
-                    )
+                    Err(Error::new(
+                        ErrorKind::InvalidRegister(value.to_owned()),
+                        span,
+                    ))
                 },
-                Self::Register,
+                |register| Ok(Self::Register(register)),
             )
         } else if let Ok(number) = value.parse::<i64>() {
             // If it isn't a register, it should be a number
             // Try to parse the number
-            Self::Number(number)
+            Ok(Self::Number(number))
         } else {
             // If it isn't a number either, it isn't a valid argument
-            panic!(
-                "Invalid argument \"{value}\": {}:{}",
-                code.line(),
-                code.column()
-            );
+            Err(Error::new(
+                ErrorKind::InvalidLiteral(value.to_owned()),
+                span,
+            ))
+        }
+    }
+
+    /// Returns the register, or an error if the value isn't a register
+    pub(crate) fn register(self, span: Span) -> Result<u8, Error> {
+        match self {
+            Self::Register(register) => Ok(register),
+            Self::Number(_) => Err(Error::new(
+                ErrorKind::WrongOperandKind {
+                    expected: "a register",
+                    found: self.to_string(),
+                },
+                span,
+            )),
         }
     }
 
     /// Take the value of the register, if ```self``` is a register
     /// Otherwise, ```self```
+    #[must_use]
     pub const fn take(&self, registers: &[i64]) -> i64 {
         match self {
             Self::Register(register) => registers[*register as usize],
@@ -72,9 +97,144 @@ impl Value {
         operation(left, right)
     }
 
+    #[must_use]
     pub fn compare(&self, other: &Self, registers: &[i64]) -> std::cmp::Ordering {
         let left = self.take(registers);
         let right = other.take(registers);
         left.cmp(&right)
     }
 }
+
+/// A memory address in a load or store instruction.
+///
+/// The address is the value of an optional base register plus the address of an optional
+/// label in the data section and an offset.
+/// Written as `[r1 + 8]`, `[r1]`, `[8]`, `[table + 8]` or `[r1 + table]`.
+/// Labels are replaced by their address when the program is assembled.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Address {
+    pub base: Option<u8>,
+    pub label: Option<String>,
+    pub offset: i64,
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_char('[')?;
+        let mut terms = 0;
+        if let Some(base) = self.base {
+            write!(f, "r{base}")?;
+            terms += 1;
+        }
+        if let Some(label) = &self.label {
+            if terms > 0 {
+                f.write_str(" + ")?;
+            }
+            f.write_str(label)?;
+            terms += 1;
+        }
+        match self.offset {
+            0 if terms > 0 => {}
+            offset if terms == 0 => write!(f, "{offset}")?,
+            offset if offset < 0 => write!(f, " - {}", offset.unsigned_abs())?,
+            offset => write!(f, " + {offset}")?,
+        }
+        f.write_char(']')
+    }
+}
+
+/// A term of a memory address
+enum Term<'a> {
+    Register(u8),
+    Label(&'a str),
+    Number(i64),
+}
+
+impl<'a> Term<'a> {
+    /// Reads a register, number or label name
+    fn from_str(term: &'a str, span: Span) -> Result<Self, Error> {
+        let is_register = term == "sp"
+            || term
+                .strip_prefix('r')
+                .is_some_and(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()));
+        if is_register {
+            let Value::Register(register) = Value::from_str(term, span)? else {
+                unreachable!("registers are parsed as registers");
+            };
+            return Ok(Self::Register(register));
+        }
+        if let Ok(number) = term.parse::<i64>() {
+            return Ok(Self::Number(number));
+        }
+        if term.is_empty() || term.contains(|c: char| c.is_whitespace() || "[]+-,:".contains(c)) {
+            return Err(Error::new(ErrorKind::InvalidAddress(term.to_owned()), span));
+        }
+        Ok(Self::Label(term))
+    }
+}
+
+impl Address {
+    pub(crate) fn from_str(address: &str, span: Span) -> Result<Self, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidAddress(address.to_owned()), span);
+
+        // Remove the brackets
+        let inner = address
+            .strip_prefix('[')
+            .and_then(|inner| inner.strip_suffix(']'))
+            .ok_or_else(invalid)?
+            .trim();
+
+        // Split at the operator, skipping the first char so negative numbers stay intact
+        let (first, second) = match inner
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| *c == '+' || *c == '-')
+        {
+            Some((index, operator)) => (
+                &inner[..index],
+                Some((operator, Term::from_str(inner[index + 1..].trim(), span)?)),
+            ),
+            None => (inner, None),
+        };
+        let first = Term::from_str(first.trim(), span)?;
+
+        // A register comes first, followed by a label or number to add,
+        // a label can be followed by a number
+        let mut result = Self {
+            base: None,
+            label: None,
+            offset: 0,
+        };
+        match first {
+            Term::Register(register) => result.base = Some(register),
+            Term::Label(label) => result.label = Some(label.to_owned()),
+            Term::Number(number) if second.is_none() => result.offset = number,
+            Term::Number(_) => return Err(invalid()),
+        }
+        match second {
+            None => {}
+            Some(('+', Term::Label(label))) if result.label.is_none() => {
+                result.label = Some(label.to_owned());
+            }
+            Some((operator, Term::Number(number))) => {
+                result.offset = if operator == '-' {
+                    number.checked_neg().ok_or_else(invalid)?
+                } else {
+                    number
+                };
+            }
+            Some(_) => return Err(invalid()),
+        }
+        Ok(result)
+    }
+
+    /// Returns the address, the value of the base register plus the offset.
+    /// Labels have to be replaced by their address before.
+    #[must_use]
+    pub const fn take(&self, registers: &[i64]) -> i64 {
+        match self.base {
+            Some(base) => registers[base as usize].wrapping_add(self.offset),
+            None => self.offset,
+        }
+    }
+}